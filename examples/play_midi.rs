@@ -61,7 +61,8 @@ impl Args {
 		let mut player = Player::new(timer, con);
 
 		println!("starting playback");
-		player.play(&sheet);
+		let playback = player.play(&sheet)?;
+		println!("played for {:?}", playback.elapsed);
 		Ok(())
 	}
 }
@@ -0,0 +1,295 @@
+use std::{fmt, ops::Range};
+
+use crate::{Event, Sheet};
+
+/// A musical position: a bar, a beat in that bar and a tick in that beat.
+///
+/// Bars and beats are numbered from 1, ticks from 0, the way sequencers show
+/// them; the first tick of a track is `1:1:0`, or in bar 0 if the track starts
+/// with a pickup (see [BarMap::with_pickup]). A beat is the note value of the
+/// time signature's denominator, so a bar of 6/8 has 6 beats.
+///
+/// Use a [BarMap] to convert between positions and ticks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position {
+	/// The bar, starting from 1; 0 is a pickup bar.
+	pub bar: u32,
+	/// The beat in the bar, starting from 1.
+	pub beat: u32,
+	/// The tick in the beat, starting from 0.
+	pub tick: u32,
+}
+
+impl Position {
+	/// Creates a new [Position].
+	pub const fn new(bar: u32, beat: u32, tick: u32) -> Self {
+		Self { bar, beat, tick }
+	}
+}
+
+impl fmt::Display for Position {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}:{}:{}", self.bar, self.beat, self.tick)
+	}
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+struct Segment {
+	tick: u32,
+	// The index of the bar starting at `tick`, from 0.
+	bar: u32,
+	numerator: u8,
+	// A power of 2.
+	denominator: u8,
+}
+
+/// Maps ticks to bars and beats, and back.
+///
+/// A [BarMap] is built once from the time signature changes of a [Sheet] and
+/// the ticks per beat found in the header of a MIDI file. Every conversion
+/// uses exact integer arithmetic.
+///
+/// # Notes
+/// - Until the first time signature change, 4/4 is assumed.
+/// - A time signature change in the middle of a bar ends that bar early and
+///   starts a new one, the same way [Sheet::into_bars] does.
+///
+/// # Examples
+/// ```
+/// use nodi::{BarMap, Event, Moment, Position, Sheet};
+///
+/// // 4/4, then 6/8 from the third bar.
+/// let sheet = Sheet::from_moments([Moment::with_events(
+///     192 * 8,
+///     vec![Event::TimeSignature(6, 3, 24, 8)],
+/// )]);
+/// let map = BarMap::new(&sheet, 192);
+///
+/// assert_eq!(map.position_at(192 * 9 + 100), Position::new(3, 4, 4));
+/// assert_eq!(map.tick_at(Position::new(4, 1, 0)), 192 * 8 + 96 * 6);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BarMap {
+	segments: Vec<Segment>,
+	ticks_per_beat: u16,
+	// The length of the pickup bar; bar 1 starts here.
+	pickup: u32,
+}
+
+impl BarMap {
+	/// Builds a [BarMap] from the time signature changes in `sheet`.
+	///
+	/// # Arguments
+	/// - `ticks_per_beat`: Obtained from a [Header](midly::Header), same value
+	///   used for constructing a [Ticker](crate::timers::Ticker).
+	pub fn new(sheet: &Sheet, ticks_per_beat: u16) -> Self {
+		Self::with_pickup(sheet, ticks_per_beat, 0)
+	}
+
+	/// Builds a [BarMap] for a track that starts with a pickup (anacrusis):
+	/// an incomplete bar of `pickup` ticks.
+	///
+	/// The pickup is bar 0 and holds the last beats of a bar, so in 4/4 a
+	/// pickup of one beat starts at `0:4:0`; bar 1 starts at tick `pickup`.
+	/// Time signature changes inside the pickup take effect from bar 1.
+	pub fn with_pickup(sheet: &Sheet, ticks_per_beat: u16, pickup: u32) -> Self {
+		let mut map = Self {
+			segments: vec![Segment {
+				tick: pickup,
+				bar: 0,
+				numerator: 4,
+				denominator: 2,
+			}],
+			ticks_per_beat,
+			pickup,
+		};
+
+		for moment in sheet.iter() {
+			for event in &moment.events {
+				if let Event::TimeSignature(numerator, denominator, ..) = *event {
+					map.push(moment.tick.max(pickup), numerator, denominator);
+				}
+			}
+		}
+
+		map
+	}
+
+	/// Returns the length of the pickup bar, 0 if there is none.
+	pub fn pickup(&self) -> u32 {
+		self.pickup
+	}
+
+	fn push(&mut self, tick: u32, numerator: u8, denominator: u8) {
+		let last = *self.segments.last().unwrap();
+		if (last.numerator, last.denominator) == (numerator, denominator) {
+			return;
+		}
+
+		let bars = (tick - last.tick).div_ceil(self.seg_len(&last));
+		let seg = Segment {
+			tick,
+			bar: last.bar + bars,
+			numerator,
+			denominator,
+		};
+		if last.tick == tick {
+			*self.segments.last_mut().unwrap() = seg;
+		} else {
+			self.segments.push(seg);
+		}
+	}
+
+	/// Returns the [Position] of `tick`.
+	pub fn position_at(&self, tick: u32) -> Position {
+		let seg = self.segment_at(tick);
+		let len = self.seg_len(seg);
+		let (bar, in_bar) = if tick < self.pickup {
+			// A pickup longer than a bar starts at its first beat.
+			(0, (tick + len).saturating_sub(self.pickup) as u64)
+		} else {
+			let offset = tick - seg.tick;
+			(seg.bar + offset / len + 1, (offset % len) as u64)
+		};
+
+		let beat = in_bar * self.beats_per_whole(seg) / self.whole();
+		Position {
+			bar,
+			beat: beat as u32 + 1,
+			tick: (in_bar - self.beat_offset(seg, beat)) as u32,
+		}
+	}
+
+	/// Returns the tick at `pos`.
+	///
+	/// Beats and ticks past the end of a bar are not an error; they count into
+	/// the following bars. Beat 0 is treated as 1, and so is bar 0 if there is
+	/// no pickup.
+	pub fn tick_at(&self, pos: Position) -> u32 {
+		let beat = pos.beat.saturating_sub(1) as u64;
+		if pos.bar == 0 && self.pickup > 0 {
+			let seg = &self.segments[0];
+			let start = self.pickup as i64 - self.seg_len(seg) as i64;
+			let tick = start + (self.beat_offset(seg, beat) + pos.tick as u64) as i64;
+			return u32::try_from(tick.max(0)).unwrap_or(u32::MAX);
+		}
+
+		let bar = pos.bar.saturating_sub(1);
+		let i = self
+			.segments
+			.partition_point(|s| s.bar <= bar)
+			.saturating_sub(1);
+		let seg = &self.segments[i];
+
+		let tick = seg.tick as u64
+			+ (bar - seg.bar) as u64 * self.seg_len(seg) as u64
+			+ self.beat_offset(seg, beat)
+			+ pos.tick as u64;
+		u32::try_from(tick).unwrap_or(u32::MAX)
+	}
+
+	/// Returns the tick bar number `bar` (starting from 1) starts at.
+	pub fn bar_start(&self, bar: u32) -> u32 {
+		self.bar_range(bar).start
+	}
+
+	/// Returns the ticks of bar number `bar`, as `start..end`.
+	///
+	/// A bar ended early by a time signature change is shorter than
+	/// [BarMap::bar_len_at]; bar 0 is the pickup, if there is one.
+	pub fn bar_range(&self, bar: u32) -> Range<u32> {
+		if bar == 0 && self.pickup > 0 {
+			return 0..self.pickup;
+		}
+		let bar = bar.max(1);
+		let start = self.tick_at(Position::new(bar, 1, 0));
+		let end = self.tick_at(Position::new(bar + 1, 1, 0));
+		start..end
+	}
+
+	/// Returns the time signature at `tick`, as `(numerator, denominator)`;
+	/// the denominator is a negative power of 2, as in
+	/// [Event::TimeSignature].
+	pub fn time_signature_at(&self, tick: u32) -> (u8, u8) {
+		let seg = self.segment_at(tick);
+		(seg.numerator, seg.denominator)
+	}
+
+	/// Returns the length of the bar at `tick`, in ticks.
+	///
+	/// This is the full length of the bar; a time signature change may end
+	/// it early.
+	pub fn bar_len_at(&self, tick: u32) -> u32 {
+		self.seg_len(self.segment_at(tick))
+	}
+
+	fn segment_at(&self, tick: u32) -> &Segment {
+		let i = self
+			.segments
+			.partition_point(|s| s.tick <= tick)
+			.saturating_sub(1);
+		&self.segments[i]
+	}
+
+	// The length of a whole note, in ticks.
+	fn whole(&self) -> u64 {
+		4 * self.ticks_per_beat as u64
+	}
+
+	fn beats_per_whole(&self, seg: &Segment) -> u64 {
+		1 << seg.denominator.min(32)
+	}
+
+	// The tick beat number `beat` (from 0) starts at, relative to its bar.
+	fn beat_offset(&self, seg: &Segment, beat: u64) -> u64 {
+		beat * self.whole() / self.beats_per_whole(seg)
+	}
+
+	// The length of a bar, rounded down; never 0.
+	fn seg_len(&self, seg: &Segment) -> u32 {
+		let len = self.beat_offset(seg, seg.numerator as u64);
+		u32::try_from(len).unwrap_or(u32::MAX).max(1)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::Moment;
+
+	#[test]
+	fn odd_meters() {
+		let sig = |tick, n, d| Moment::with_events(tick, vec![Event::TimeSignature(n, d, 24, 8)]);
+		// 7/8 from the start, 3/4 in the middle of the second bar, 5/16 later.
+		let sheet =
+			Sheet::from_moments([sig(0, 7, 3), sig(420 + 200, 3, 2), sig(620 + 360 * 2, 5, 4)]);
+		let map = BarMap::new(&sheet, 120);
+
+		assert_eq!(map.bar_len_at(0), 420);
+		assert_eq!(map.position_at(419), Position::new(1, 7, 59));
+		assert_eq!(map.position_at(619), Position::new(2, 4, 19));
+		assert_eq!(map.position_at(620), Position::new(3, 1, 0));
+		assert_eq!(map.position_at(620 + 360 + 130), Position::new(4, 2, 10));
+		assert_eq!(map.position_at(1340 + 150 * 3 + 31), Position::new(8, 2, 1));
+		assert_eq!(map.time_signature_at(1340), (5, 4));
+
+		for tick in [0, 419, 420, 619, 620, 1000, 1339, 1340, 5000] {
+			assert_eq!(map.tick_at(map.position_at(tick)), tick);
+		}
+		assert_eq!(map.bar_start(3), 620);
+		assert_eq!(map.position_at(619).to_string(), "2:4:19");
+		assert_eq!(map.bar_range(2), 420..620);
+	}
+
+	#[test]
+	fn pickup() {
+		let map = BarMap::with_pickup(&Sheet::new(), 96, 96 + 48);
+		assert_eq!(map.position_at(0), Position::new(0, 3, 48));
+		assert_eq!(map.position_at(100), Position::new(0, 4, 52));
+		assert_eq!(map.position_at(144), Position::new(1, 1, 0));
+		assert_eq!(map.tick_at(Position::new(0, 4, 52)), 100);
+		assert_eq!(map.tick_at(Position::new(0, 1, 0)), 0);
+		assert_eq!(map.bar_range(0), 0..144);
+		assert_eq!(map.bar_range(1), 144..144 + 384);
+	}
+}
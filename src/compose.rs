@@ -1,6 +1,61 @@
 //! Contains various small types that implement [Connection] that add extra capabilities to another [Connection] by wrapping them.
+//!
+//! Every wrapper forwards SysEx, raw, system realtime and system common messages,
+//! as well as [Connection::all_notes_off], to the connection(s) it wraps
+//! untouched; only [Connection::play] is affected.
+//!
+//! Since each combinator wraps the connection it's called on, the last one
+//! added is the first to see an event.
+//!
+//! # Examples
+//! ```
+//! use nodi::{compose::Compose, Recorder};
+//!
+//! // Play everything on channel 3, a bit softer, and print what is played.
+//! let con = Recorder::default()
+//!     .inspect(|e| println!("{:?}", e))
+//!     .velocity(|vel| (vel.as_int() * 3 / 4).into())
+//!     .remap_channels([3.into(); 16]);
+//! ```
 
-use crate::{Connection, MidiEvent};
+use midly::{
+	live::{SystemCommon, SystemRealtime},
+	num::{u4, u7},
+	MidiMessage,
+};
+
+use crate::{Connection, ConnectionError, MidiEvent};
+
+/// Implements the forwarding methods of [Connection] for a wrapper with a
+/// `con` field.
+macro_rules! forward {
+	() => {
+		#[inline]
+		fn send_sysex(&mut self, data: &[u8]) -> Result<(), ConnectionError> {
+			self.con.send_sysex(data)
+		}
+
+		#[inline]
+		fn send_raw(&mut self, data: &[u8]) -> Result<(), ConnectionError> {
+			self.con.send_raw(data)
+		}
+
+		#[inline]
+		fn send_sys_rt(&mut self, msg: SystemRealtime) -> Result<(), ConnectionError> {
+			self.con.send_sys_rt(msg)
+		}
+
+		#[inline]
+		fn send_sys_common(&mut self, msg: SystemCommon<'_>) -> Result<(), ConnectionError> {
+			self.con.send_sys_common(msg)
+		}
+
+		#[inline]
+		fn all_notes_off(&mut self) -> Result<(), ConnectionError> {
+			self.con.all_notes_off()
+		}
+	};
+}
 
 /// [Connection] combinators.
 ///
@@ -14,13 +69,61 @@ pub trait Compose: Connection + Sized {
 	/// Returns a [Connection] that conditionally calls `self` to play its input.
 	///
 	/// The event is played if `f(&event) == true`.
-	/// If the closure returns false, the event will be skipped but the return value will still be `true`.
+	/// If the closure returns false, the event will be skipped but the return value will still be `Ok(())`.
 	fn filter<F>(self, f: F) -> Filter<Self, F>
 	where
 		F: for<'a> FnMut(&'a MidiEvent) -> bool,
 	{
 		Filter { con: self, f }
 	}
+
+	/// Returns a [Connection] that maps its input and plays it with `self` if
+	/// `f` returns [Some].
+	///
+	/// Skipped events are not errors, the return value will be `Ok(())`.
+	fn filter_map<F: FnMut(MidiEvent) -> Option<MidiEvent>>(self, f: F) -> FilterMap<Self, F> {
+		FilterMap { con: self, f }
+	}
+
+	/// Returns a [Connection] that calls `f` with every event before playing it
+	/// with `self`.
+	fn inspect<F>(self, f: F) -> Inspect<Self, F>
+	where
+		F: for<'a> FnMut(&'a MidiEvent),
+	{
+		Inspect { con: self, f }
+	}
+
+	/// Returns a [Connection] that plays every event with both `self` and
+	/// `other`.
+	///
+	/// Both connections are always sent the event; if either fails, the first
+	/// error is returned. To stop at the first error, use [Compose::then].
+	fn tee<C: Connection>(self, other: C) -> Tee<Self, C> {
+		Tee { con: self, other }
+	}
+
+	/// Returns a [Connection] that plays every event with `self`, then with
+	/// `other` if `self` succeeded.
+	fn then<C: Connection>(self, other: C) -> Then<Self, C> {
+		Then { con: self, other }
+	}
+
+	/// Returns a [Connection] that moves every event on channel `n` to channel
+	/// `map[n]` before playing it with `self`.
+	fn remap_channels(self, map: [u4; 16]) -> RemapChannels<Self> {
+		RemapChannels { con: self, map }
+	}
+
+	/// Returns a [Connection] that changes the velocity of every NoteOn with
+	/// `f` before playing it with `self`.
+	///
+	/// NoteOn messages with a velocity of 0 are NoteOffs and are left alone; if
+	/// `f` returns 0 for any other velocity, 1 is used instead so that the note
+	/// still plays.
+	fn velocity<F: FnMut(u7) -> u7>(self, f: F) -> Velocity<Self, F> {
+		Velocity { con: self, f }
+	}
 }
 
 impl<C: Connection> Compose for C {}
@@ -35,10 +138,12 @@ pub struct Map<C: Connection, F> {
 
 impl<C: Connection, F: FnMut(MidiEvent) -> MidiEvent> Connection for Map<C, F> {
 	#[inline]
-	fn play(&mut self, event: MidiEvent) -> bool {
+	fn play(&mut self, event: MidiEvent) -> Result<(), ConnectionError> {
 		let e = (self.f)(event);
 		self.con.play(e)
 	}
+
+	forward!();
 }
 
 /// A filtering [Connection]. Created by calling [Compose::filter] on an existing connection.
@@ -54,11 +159,252 @@ where
 	F: for<'a> FnMut(&'a MidiEvent) -> bool,
 {
 	#[inline]
-	fn play(&mut self, event: MidiEvent) -> bool {
+	fn play(&mut self, event: MidiEvent) -> Result<(), ConnectionError> {
 		if (self.f)(&event) {
 			self.con.play(event)
 		} else {
-			true
+			Ok(())
+		}
+	}
+
+	forward!();
+}
+
+/// A filtering and mapping [Connection]. Created by calling [Compose::filter_map] on an existing connection.
+#[derive(Clone, Debug)]
+pub struct FilterMap<C: Connection, F> {
+	/// The wrapped [Connection].
+	pub con: C,
+	f: F,
+}
+
+impl<C: Connection, F: FnMut(MidiEvent) -> Option<MidiEvent>> Connection for FilterMap<C, F> {
+	#[inline]
+	fn play(&mut self, event: MidiEvent) -> Result<(), ConnectionError> {
+		match (self.f)(event) {
+			Some(e) => self.con.play(e),
+			None => Ok(()),
+		}
+	}
+
+	forward!();
+}
+
+/// An inspecting [Connection]. Created by calling [Compose::inspect] on an existing connection.
+#[derive(Clone, Debug)]
+pub struct Inspect<C: Connection, F> {
+	/// The wrapped [Connection].
+	pub con: C,
+	f: F,
+}
+
+impl<C: Connection, F> Connection for Inspect<C, F>
+where
+	F: for<'a> FnMut(&'a MidiEvent),
+{
+	#[inline]
+	fn play(&mut self, event: MidiEvent) -> Result<(), ConnectionError> {
+		(self.f)(&event);
+		self.con.play(event)
+	}
+
+	forward!();
+}
+
+/// A [Connection] sending to two others. Created by calling [Compose::tee] on an existing connection.
+#[derive(Clone, Debug)]
+pub struct Tee<A: Connection, B: Connection> {
+	/// The first wrapped [Connection].
+	pub con: A,
+	/// The second wrapped [Connection].
+	pub other: B,
+}
+
+impl<A: Connection, B: Connection> Connection for Tee<A, B> {
+	#[inline]
+	fn play(&mut self, event: MidiEvent) -> Result<(), ConnectionError> {
+		let a = self.con.play(event);
+		let b = self.other.play(event);
+		a.and(b)
+	}
+
+	fn send_sysex(&mut self, data: &[u8]) -> Result<(), ConnectionError> {
+		let a = self.con.send_sysex(data);
+		let b = self.other.send_sysex(data);
+		a.and(b)
+	}
+
+	fn send_raw(&mut self, data: &[u8]) -> Result<(), ConnectionError> {
+		let a = self.con.send_raw(data);
+		let b = self.other.send_raw(data);
+		a.and(b)
+	}
+
+	fn send_sys_rt(&mut self, msg: SystemRealtime) -> Result<(), ConnectionError> {
+		let a = self.con.send_sys_rt(msg);
+		let b = self.other.send_sys_rt(msg);
+		a.and(b)
+	}
+
+	fn send_sys_common(&mut self, msg: SystemCommon<'_>) -> Result<(), ConnectionError> {
+		let a = self.con.send_sys_common(msg);
+		let b = self.other.send_sys_common(msg);
+		a.and(b)
+	}
+
+	fn all_notes_off(&mut self) -> Result<(), ConnectionError> {
+		let a = self.con.all_notes_off();
+		let b = self.other.all_notes_off();
+		a.and(b)
+	}
+}
+
+/// A [Connection] sending to two others in turn. Created by calling [Compose::then] on an existing connection.
+#[derive(Clone, Debug)]
+pub struct Then<A: Connection, B: Connection> {
+	/// The first wrapped [Connection].
+	pub con: A,
+	/// The second wrapped [Connection], used if the first one succeeds.
+	pub other: B,
+}
+
+impl<A: Connection, B: Connection> Connection for Then<A, B> {
+	#[inline]
+	fn play(&mut self, event: MidiEvent) -> Result<(), ConnectionError> {
+		self.con.play(event)?;
+		self.other.play(event)
+	}
+
+	fn send_sysex(&mut self, data: &[u8]) -> Result<(), ConnectionError> {
+		self.con.send_sysex(data)?;
+		self.other.send_sysex(data)
+	}
+
+	fn send_raw(&mut self, data: &[u8]) -> Result<(), ConnectionError> {
+		self.con.send_raw(data)?;
+		self.other.send_raw(data)
+	}
+
+	fn send_sys_rt(&mut self, msg: SystemRealtime) -> Result<(), ConnectionError> {
+		self.con.send_sys_rt(msg)?;
+		self.other.send_sys_rt(msg)
+	}
+
+	fn send_sys_common(&mut self, msg: SystemCommon<'_>) -> Result<(), ConnectionError> {
+		self.con.send_sys_common(msg)?;
+		self.other.send_sys_common(msg)
+	}
+
+	fn all_notes_off(&mut self) -> Result<(), ConnectionError> {
+		self.con.all_notes_off()?;
+		self.other.all_notes_off()
+	}
+}
+
+/// A channel remapping [Connection]. Created by calling [Compose::remap_channels] on an existing connection.
+#[derive(Clone, Debug)]
+pub struct RemapChannels<C: Connection> {
+	/// The wrapped [Connection].
+	pub con: C,
+	/// The channel every channel is moved to, indexed by the original channel.
+	pub map: [u4; 16],
+}
+
+impl<C: Connection> Connection for RemapChannels<C> {
+	#[inline]
+	fn play(&mut self, mut event: MidiEvent) -> Result<(), ConnectionError> {
+		event.channel = self.map[event.channel.as_int() as usize];
+		self.con.play(event)
+	}
+
+	forward!();
+}
+
+/// A [Connection] applying a velocity curve. Created by calling [Compose::velocity] on an existing connection.
+#[derive(Clone, Debug)]
+pub struct Velocity<C: Connection, F> {
+	/// The wrapped [Connection].
+	pub con: C,
+	f: F,
+}
+
+impl<C: Connection, F: FnMut(u7) -> u7> Connection for Velocity<C, F> {
+	#[inline]
+	fn play(&mut self, mut event: MidiEvent) -> Result<(), ConnectionError> {
+		if let MidiMessage::NoteOn { vel, .. } = &mut event.message {
+			if *vel > 0 {
+				*vel = (self.f)(*vel).max(1.into());
+			}
+		}
+		self.con.play(event)
+	}
+
+	forward!();
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::Recorder;
+
+	fn note_on(channel: u8, vel: u8) -> MidiEvent {
+		MidiEvent {
+			channel: channel.into(),
+			message: MidiMessage::NoteOn {
+				key: 60.into(),
+				vel: vel.into(),
+			},
+		}
+	}
+
+	struct Fail;
+
+	impl Connection for Fail {
+		fn play(&mut self, _: MidiEvent) -> Result<(), ConnectionError> {
+			Err(ConnectionError::Disconnected)
+		}
+	}
+
+	#[test]
+	fn combinators() {
+		let mut map = [0.into(); 16];
+		map[2] = 5.into();
+		let mut seen = 0;
+
+		let mut con = Recorder::default()
+			// Sees the channels after they are remapped.
+			.filter_map(|e| (e.channel != 0).then_some(e))
+			.remap_channels(map)
+			.velocity(|vel| (vel.as_int() / 2).into())
+			.inspect(|_| seen += 1)
+			.tee(Recorder::default());
+
+		for e in [
+			note_on(2, 100),
+			note_on(1, 100),
+			note_on(2, 1),
+			note_on(2, 0),
+		] {
+			con.play(e).unwrap();
 		}
+		con.send_sysex(&[0x7e, 0xf7]).unwrap();
+
+		let inner = &con.con.con.con.con.con;
+		assert_eq!(
+			inner.events.iter().map(|(_, e)| *e).collect::<Vec<_>>(),
+			[note_on(5, 50), note_on(5, 1), note_on(5, 0)]
+		);
+		assert_eq!(inner.sysex.len(), 1);
+		assert_eq!(con.other.events.len(), 4);
+		assert_eq!(con.other.sysex.len(), 1);
+		drop(con);
+		assert_eq!(seen, 4);
+
+		let mut tee = Fail.tee(Recorder::default());
+		assert!(tee.play(note_on(0, 1)).is_err());
+		assert_eq!(tee.other.events.len(), 1);
+		let mut then = Fail.then(Recorder::default());
+		assert!(then.play(note_on(0, 1)).is_err());
+		assert!(then.other.events.is_empty());
 	}
 }
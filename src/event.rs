@@ -1,3 +1,4 @@
+mod order;
 mod transpose;
 
 use std::{
@@ -6,15 +7,45 @@ use std::{
 	ops::{Deref, DerefMut},
 };
 
-use midly::{live::LiveEvent, num::u4, MetaMessage, MidiMessage, TrackEventKind};
+use midly::{
+	live::LiveEvent,
+	num::{u24, u4, u7},
+	MetaMessage, MidiMessage, SmpteTime, TrackEventKind,
+};
+
+pub use order::EventOrder;
+pub(crate) use order::{merge_events, merge_sources};
 
 /// Represents a single moment (tick) in a MIDI track.
+///
+/// A [Moment] knows its absolute position in the track, see [Moment::tick].
 #[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
-pub struct Moment {
+pub struct Moment {
+	pub(crate) tick: u32,
 	/// Events in this moment.
 	pub events: Vec<Event>,
 }
 
+impl Moment {
+	/// Creates an empty [Moment] positioned at `tick`.
+	pub const fn new(tick: u32) -> Self {
+		Self {
+			tick,
+			events: Vec::new(),
+		}
+	}
+
+	/// Creates a [Moment] positioned at `tick`, holding `events`.
+	pub fn with_events(tick: u32, events: Vec<Event>) -> Self {
+		Self { tick, events }
+	}
+
+	/// Returns the absolute position of this moment, in MIDI ticks.
+	pub fn tick(&self) -> u32 {
+		self.tick
+	}
+}
+
 impl Deref for Moment {
 	type Target = Vec<Event>;
 	fn deref(&self) -> &Self::Target {
@@ -29,7 +60,10 @@ impl DerefMut for Moment {
 }
 
 /// Represents a single MIDI event.
-#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+///
+/// Events that carry data own it, so a [Sheet](crate::Sheet) can outlive the
+/// bytes of the file it was parsed from.
+#[derive(Clone, Eq, PartialEq, Debug, Hash)]
 pub enum Event {
 	/// Represents a tempo change message.
 	/// The wrapped `u32` represents microseconds per beat.
@@ -43,6 +77,43 @@ pub enum Event {
 	KeySignature(i8, bool),
 	/// Represents a MIDI event.
 	Midi(MidiEvent),
+	/// A System Exclusive message.
+	///
+	/// The data does not include the leading `0xF0` byte. It usually ends
+	/// with `0xF7`, unless the message is split into several packets.
+	SysEx(Vec<u8>),
+	/// An escape sequence; arbitrary data to be sent to a device as is.
+	Escape(Vec<u8>),
+	/// The sequence number of a track.
+	TrackNumber(Option<u16>),
+	/// Arbitrary text.
+	Text(Vec<u8>),
+	/// A copyright notice.
+	Copyright(Vec<u8>),
+	/// The name of the track.
+	TrackName(Vec<u8>),
+	/// The name of the instrument used in the track.
+	InstrumentName(Vec<u8>),
+	/// A lyric, usually a syllable.
+	Lyric(Vec<u8>),
+	/// A marker, such as a rehearsal letter or a section name.
+	Marker(Vec<u8>),
+	/// A cue point, describing something happening on stage.
+	CuePoint(Vec<u8>),
+	/// The name of the program (patch) in use.
+	ProgramName(Vec<u8>),
+	/// The name of the device the track is intended for.
+	DeviceName(Vec<u8>),
+	/// The MIDI channel the following meta events are associated with.
+	MidiChannel(u4),
+	/// The MIDI port the track is intended for.
+	MidiPort(u7),
+	/// The SMPTE time the track is supposed to start at.
+	SmpteOffset(SmpteTime),
+	/// Data specific to a sequencer; this is never sent to a device.
+	SequencerSpecific(Vec<u8>),
+	/// An unknown meta event, with its type byte and data.
+	UnknownMeta(u8, Vec<u8>),
 }
 
 /// Represents a MIDI message.
@@ -83,16 +154,124 @@ impl TryFrom<TrackEventKind<'_>> for Event {
 	/// Tries to create [Self] from a [TrackEventKind].
 	///
 	/// # Errors
-	/// Will return an error if the given [TrackEventKind] is not compatible.
+	/// Will return an error if the given [TrackEventKind] is an End-of-Track
+	/// event; the end of a track is represented by [Sheet::len](crate::Sheet::len).
 	fn try_from(event: TrackEventKind<'_>) -> Result<Self, Self::Error> {
 		Ok(match event {
 			TrackEventKind::Midi { channel, message } => Self::Midi(MidiEvent { channel, message }),
-			TrackEventKind::Meta(MetaMessage::Tempo(n)) => Self::Tempo(u32::from(n)),
-			TrackEventKind::Meta(MetaMessage::TimeSignature(a, b, c, d)) => {
-				Self::TimeSignature(a, b, c, d)
-			}
-			TrackEventKind::Meta(MetaMessage::KeySignature(a, b)) => Self::KeySignature(a, b),
-			_ => return Err("not a valid event"),
+			TrackEventKind::SysEx(data) => Self::SysEx(data.to_vec()),
+			TrackEventKind::Escape(data) => Self::Escape(data.to_vec()),
+			TrackEventKind::Meta(msg) => match msg {
+				MetaMessage::Tempo(n) => Self::Tempo(u32::from(n)),
+				MetaMessage::TimeSignature(a, b, c, d) => Self::TimeSignature(a, b, c, d),
+				MetaMessage::KeySignature(a, b) => Self::KeySignature(a, b),
+				MetaMessage::TrackNumber(n) => Self::TrackNumber(n),
+				MetaMessage::Text(s) => Self::Text(s.to_vec()),
+				MetaMessage::Copyright(s) => Self::Copyright(s.to_vec()),
+				MetaMessage::TrackName(s) => Self::TrackName(s.to_vec()),
+				MetaMessage::InstrumentName(s) => Self::InstrumentName(s.to_vec()),
+				MetaMessage::Lyric(s) => Self::Lyric(s.to_vec()),
+				MetaMessage::Marker(s) => Self::Marker(s.to_vec()),
+				MetaMessage::CuePoint(s) => Self::CuePoint(s.to_vec()),
+				MetaMessage::ProgramName(s) => Self::ProgramName(s.to_vec()),
+				MetaMessage::DeviceName(s) => Self::DeviceName(s.to_vec()),
+				MetaMessage::MidiChannel(ch) => Self::MidiChannel(ch),
+				MetaMessage::MidiPort(port) => Self::MidiPort(port),
+				MetaMessage::SmpteOffset(t) => Self::SmpteOffset(t),
+				MetaMessage::SequencerSpecific(data) => Self::SequencerSpecific(data.to_vec()),
+				MetaMessage::Unknown(kind, data) => Self::UnknownMeta(kind, data.to_vec()),
+				MetaMessage::EndOfTrack => return Err("not a valid event"),
+			},
 		})
 	}
 }
+
+impl<'a> From<&'a Event> for TrackEventKind<'a> {
+	/// Converts an [Event] back into a [TrackEventKind], so that it can be
+	/// written to a MIDI file.
+	fn from(event: &'a Event) -> Self {
+		let meta = match event {
+			Event::Midi(MidiEvent { channel, message }) => {
+				return Self::Midi {
+					channel: *channel,
+					message: *message,
+				}
+			}
+			Event::SysEx(data) => return Self::SysEx(data),
+			Event::Escape(data) => return Self::Escape(data),
+			Event::Tempo(n) => MetaMessage::Tempo(u24::new(*n)),
+			Event::TimeSignature(a, b, c, d) => MetaMessage::TimeSignature(*a, *b, *c, *d),
+			Event::KeySignature(a, b) => MetaMessage::KeySignature(*a, *b),
+			Event::TrackNumber(n) => MetaMessage::TrackNumber(*n),
+			Event::Text(s) => MetaMessage::Text(s),
+			Event::Copyright(s) => MetaMessage::Copyright(s),
+			Event::TrackName(s) => MetaMessage::TrackName(s),
+			Event::InstrumentName(s) => MetaMessage::InstrumentName(s),
+			Event::Lyric(s) => MetaMessage::Lyric(s),
+			Event::Marker(s) => MetaMessage::Marker(s),
+			Event::CuePoint(s) => MetaMessage::CuePoint(s),
+			Event::ProgramName(s) => MetaMessage::ProgramName(s),
+			Event::DeviceName(s) => MetaMessage::DeviceName(s),
+			Event::MidiChannel(ch) => MetaMessage::MidiChannel(*ch),
+			Event::MidiPort(port) => MetaMessage::MidiPort(*port),
+			Event::SmpteOffset(t) => MetaMessage::SmpteOffset(*t),
+			Event::SequencerSpecific(data) => MetaMessage::SequencerSpecific(data),
+			Event::UnknownMeta(kind, data) => MetaMessage::Unknown(*kind, data),
+		};
+
+		Self::Meta(meta)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use midly::{num::u28, TrackEvent};
+
+	use super::*;
+	use crate::{timers::Ticker, Connection, ConnectionError, Player, Sheet};
+
+	// Records the bytes a device would receive.
+	#[derive(Default)]
+	struct Wire(Vec<u8>);
+
+	impl Connection for Wire {
+		fn play(&mut self, _: MidiEvent) -> Result<(), ConnectionError> {
+			Ok(())
+		}
+
+		fn send_sysex(&mut self, data: &[u8]) -> Result<(), ConnectionError> {
+			self.0.push(0xf0);
+			self.0.extend_from_slice(data);
+			Ok(())
+		}
+
+		fn send_raw(&mut self, data: &[u8]) -> Result<(), ConnectionError> {
+			self.0.extend_from_slice(data);
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn split_sysex() {
+		let ev = |delta: u32, kind| TrackEvent {
+			delta: u28::new(delta),
+			kind,
+		};
+		let track = [
+			ev(0, TrackEventKind::SysEx(&[0x43, 0x12, 0x00])),
+			ev(10, TrackEventKind::Escape(&[0x07, 0x01])),
+			ev(10, TrackEventKind::Escape(&[0x02, 0xf7])),
+		];
+		let sheet = Sheet::from(&track[..]);
+		assert_eq!(sheet[0].events, [Event::SysEx(vec![0x43, 0x12, 0x00])]);
+		assert_eq!(sheet[10].events, [Event::Escape(vec![0x07, 0x01])]);
+		assert_eq!(sheet[20].events, [Event::Escape(vec![0x02, 0xf7])]);
+
+		let mut player = Player::new(Ticker::new(96), Wire::default());
+		player.play(&sheet).unwrap();
+		assert_eq!(
+			player.con.0,
+			[0xf0, 0x43, 0x12, 0x00, 0x07, 0x01, 0x02, 0xf7]
+		);
+	}
+}
@@ -0,0 +1,160 @@
+use std::mem;
+
+use midly::MidiMessage;
+
+use super::{Event, MidiEvent, Moment};
+
+/// How the events of a [Moment] are ordered when sheets are merged.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum EventOrder {
+	/// Sort the events as [Moment::sort_events] does.
+	#[default]
+	Canonical,
+	/// Keep the events of the first sheet, followed by the events of the
+	/// second sheet.
+	Preserve,
+}
+
+impl Moment {
+	/// Sorts the events in this moment in a canonical order, so that they play
+	/// as intended regardless of which track they came from:
+	///
+	/// 1. Meta events, such as tempo and time signature changes.
+	/// 2. SysEx messages.
+	/// 3. Bank selects (controllers 0 and 32).
+	/// 4. Program changes.
+	/// 5. Other controllers, pitch bends and channel pressure.
+	/// 6. NoteOffs (including NoteOns with a velocity of 0).
+	/// 7. NoteOns and polyphonic key pressure.
+	///
+	/// The sort is stable: events in the same group keep their order. A
+	/// NoteOff that ends a note started earlier in this same moment (a note
+	/// with a duration of 0) is kept after that NoteOn.
+	pub fn sort_events(&mut self) {
+		let events = mem::take(&mut self.events);
+		self.events = sorted(ranked(events));
+	}
+}
+
+// Merges `b` into `a`, sorting canonically; each side keeps its own notes of
+// duration 0.
+pub(crate) fn merge_events(a: &mut Vec<Event>, b: Vec<Event>, order: EventOrder) {
+	match order {
+		EventOrder::Preserve => a.extend(b),
+		EventOrder::Canonical => {
+			let mut ranked = ranked(mem::take(a));
+			ranked.extend(self::ranked(b));
+			*a = sorted(ranked);
+		}
+	}
+}
+
+// Merges the events several sources have at the same tick, sorting
+// canonically; every event is returned with the index of its source.
+pub(crate) fn merge_sources(sources: Vec<(usize, Vec<Event>)>) -> Vec<(usize, Event)> {
+	let mut buf = sources
+		.into_iter()
+		.flat_map(|(src, events)| {
+			ranked(events)
+				.into_iter()
+				.map(move |(rank, e)| (rank, src, e))
+		})
+		.collect::<Vec<_>>();
+	buf.sort_by_key(|(rank, ..)| *rank);
+	buf.into_iter().map(|(_, src, e)| (src, e)).collect()
+}
+
+fn sorted(mut ranked: Vec<(u8, Event)>) -> Vec<Event> {
+	ranked.sort_by_key(|(rank, _)| *rank);
+	ranked.into_iter().map(|(_, e)| e).collect()
+}
+
+fn ranked(events: Vec<Event>) -> Vec<(u8, Event)> {
+	// Keys started so far, per channel.
+	let mut started = [0_u128; 16];
+
+	events
+		.into_iter()
+		.map(|e| {
+			let rank = match &e {
+				Event::SysEx(_) | Event::Escape(_) => 1,
+				Event::Midi(MidiEvent { channel, message }) => {
+					let ch = channel.as_int() as usize;
+					match *message {
+						MidiMessage::Controller { controller, .. }
+							if controller == 0 || controller == 32 =>
+						{
+							2
+						}
+						MidiMessage::ProgramChange { .. } => 3,
+						MidiMessage::NoteOn { key, vel } if vel > 0 => {
+							started[ch] |= 1 << key.as_int();
+							6
+						}
+						MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+							if started[ch] & (1 << key.as_int()) != 0 {
+								7
+							} else {
+								5
+							}
+						}
+						MidiMessage::Aftertouch { .. } => 6,
+						_ => 4,
+					}
+				}
+				_ => 0,
+			};
+			(rank, e)
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn midi(message: MidiMessage) -> Event {
+		Event::Midi(MidiEvent {
+			channel: 0.into(),
+			message,
+		})
+	}
+
+	fn on(key: u8, vel: u8) -> Event {
+		midi(MidiMessage::NoteOn {
+			key: key.into(),
+			vel: vel.into(),
+		})
+	}
+
+	#[test]
+	fn canonical_order() {
+		let program = midi(MidiMessage::ProgramChange { program: 3.into() });
+		let bank = midi(MidiMessage::Controller {
+			controller: 0.into(),
+			value: 1.into(),
+		});
+		let mut a = vec![on(60, 100), on(62, 100), on(62, 0)];
+		let b = vec![on(60, 0), program.clone(), Event::Tempo(1), bank.clone()];
+
+		let mut preserved = a.clone();
+		merge_events(&mut preserved, b.clone(), EventOrder::Preserve);
+		assert_eq!(preserved[3], on(60, 0));
+
+		merge_events(&mut a, b, EventOrder::Canonical);
+		let sorted = vec![
+			Event::Tempo(1),
+			bank,
+			program,
+			on(60, 0),
+			on(60, 100),
+			on(62, 100),
+			on(62, 0),
+		];
+		assert_eq!(a, sorted);
+
+		let mut moment = Moment::with_events(0, a);
+		moment.sort_events();
+		assert_eq!(moment.events, sorted);
+	}
+}
@@ -83,6 +83,7 @@ mod tests {
 	fn transpose() {
 		fn new_moment(range: std::ops::RangeInclusive<i32>) -> Moment {
 			Moment {
+				tick: 0,
 				events: range
 					.map(|n| {
 						Event::Midi(MidiEvent {
@@ -2,14 +2,18 @@
 #![warn(missing_docs, rustdoc::missing_crate_level_docs)]
 #![doc = include_str!("doc_lib.md")]
 
+mod bar_map;
+pub mod compose;
 mod event;
 mod player;
 mod sheet;
+mod song;
+mod tempo_map;
 pub mod timers;
 
 use std::time::Duration;
 
-pub use self::{event::*, player::*, sheet::*};
+pub use self::{bar_map::*, event::*, player::*, sheet::*, song::*, tempo_map::*};
 #[cfg(feature = "midir")]
 pub use midir;
 pub use midly;
@@ -30,6 +34,31 @@ pub trait Timer {
 	/// - `tempo`: Represents microseconds per a beat (MIDI quarter note).
 	fn change_tempo(&mut self, tempo: u32);
 
+	/// Returns the [Duration] of `n_ticks` ticks at the current tempo and
+	/// speed, ignoring any drift compensation.
+	///
+	/// Unlike [Timer::sleep_duration], calling this must not change the state
+	/// of the timer. The provided implementation calls
+	/// [Timer::sleep_duration], which is correct for timers that don't
+	/// compensate for drift.
+	fn nominal_duration(&mut self, n_ticks: u32) -> Duration {
+		self.sleep_duration(n_ticks)
+	}
+
+	/// Changes the speed of the timer; `1.0` is the normal speed.
+	///
+	/// This is called by [Player] when [PlayerHandle::set_speed] is used.
+	/// The provided implementation does nothing, meaning the timer does not
+	/// support speed changes.
+	fn set_speed(&mut self, _speed: f32) {}
+
+	/// Forgets any timing state that depends on the previous sleep.
+	///
+	/// [Player] calls this after a discontinuity in playback, such as a pause
+	/// or a seek, so that timers which compensate for drift don't try to catch
+	/// up. The provided implementation does nothing.
+	fn reset(&mut self) {}
+
 	/// Sleeps given number of ticks.
 	/// The provided implementation will sleep the thread  for
 	/// `self.sleep_duration(n_ticks)`.
@@ -37,6 +66,9 @@ pub trait Timer {
 	/// # Notes
 	/// The provided implementation will not sleep if
 	/// `self.sleep_duration(n_ticks).is_zero()`.
+	///
+	/// [Player] only calls this while no [PlayerHandle] exists, see
+	/// [Timer::sleep_with].
 	fn sleep(&mut self, n_ticks: u32) {
 		let t = self.sleep_duration(n_ticks);
 
@@ -45,14 +77,35 @@ pub trait Timer {
 		}
 	}
 
+	/// Sleeps given number of ticks by calling `wait`, which can be
+	/// interrupted.
+	///
+	/// [Player] calls this instead of [Timer::sleep] while a [PlayerHandle]
+	/// exists, so that it can react to commands while sleeping. `wait(t)`
+	/// blocks for at most `t`, staying paused as long as requested, and
+	/// returns `false` if playback was stopped or moved with a seek; the
+	/// timer should then return right away.
+	///
+	/// The provided implementation calls `wait` once with
+	/// `self.sleep_duration(n_ticks)`. Timers that override [Timer::sleep]
+	/// to block on something else, or whose sleep can change while waiting,
+	/// should override this too, waiting through `wait` in steps.
+	fn sleep_with(&mut self, n_ticks: u32, wait: &mut dyn FnMut(Duration) -> bool) {
+		wait(self.sleep_duration(n_ticks));
+	}
+
 	/// Calculates the length of a track or a slice of [Moment]s.
 	///
+	/// The length is measured from tick 0 to the last moment in the slice.
+	///
 	/// # Notes
 	/// The default implementation modifies `self` if a tempo event is found.
 	fn duration(&mut self, moments: &[Moment]) -> Duration {
 		let mut counter = Duration::default();
+		let mut last_tick = 0;
 		for moment in moments {
-			counter += self.sleep_duration(1);
+			counter += self.sleep_duration(moment.tick() - last_tick);
+			last_tick = moment.tick();
 			for event in &moment.events {
 				if let Event::Tempo(val) = event {
 					self.change_tempo(*val);
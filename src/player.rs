@@ -1,27 +1,121 @@
+use std::{
+	sync::Arc,
+	time::{Duration, Instant},
+};
+
 #[cfg(feature = "midir")]
 use midir::{self, MidiOutputConnection};
 use midly::{
 	live::{SystemCommon, SystemRealtime},
+	num::u4,
 	MidiMessage,
 };
 
 use crate::{
 	event::{Event, MidiEvent, Moment},
-	Timer,
+	Sheet, Song, Timer,
 };
 
+mod active;
+mod chase;
+mod clock;
+mod error;
+mod handle;
+mod looping;
+mod mix;
+mod playlist;
+mod recorder;
+mod render;
+
+pub use active::{ActiveNotes, Release};
+pub use chase::ChaseState;
+pub use error::{ConnectionError, PlayError, Playback, PlaybackEnd};
+pub use handle::{PlaybackState, PlayerHandle};
+pub use looping::Loop;
+pub use playlist::{Playlist, PlaylistEvent, RepeatMode};
+pub use recorder::Recorder;
+pub use render::Render;
+
+pub(crate) use clock::{CLOCKS_PER_SPP, PPQN};
+pub(crate) use handle::WeakHandle;
+
+use clock::BeatClock;
+use handle::{Interrupt, Shared};
+use mix::Mix;
+
 #[doc = include_str!("doc_player.md")]
 pub struct Player<T: Timer, C: Connection> {
 	/// An active midi connection.
 	pub con: C,
 	timer: T,
+	shared: Arc<Shared>,
+	active: ActiveNotes,
+	release: Release,
+	mix: Mix,
+	region: Option<Loop>,
+	clock: Option<BeatClock>,
 }
 
 impl<T: Timer, C: Connection> Player<T, C> {
 	/// Creates a new [Player] with the given [Timer] and
 	/// [Connection].
 	pub fn new(timer: T, con: C) -> Self {
-		Self { con, timer }
+		Self {
+			con,
+			timer,
+			shared: Arc::default(),
+			active: ActiveNotes::new(),
+			release: Release::default(),
+			mix: Mix::default(),
+			region: None,
+			clock: None,
+		}
+	}
+
+	/// Returns a [PlayerHandle] for controlling playback from other threads.
+	pub fn handle(&self) -> PlayerHandle {
+		PlayerHandle::new(Arc::clone(&self.shared))
+	}
+
+	/// Sets what is sent when playback is stopped, paused, moved with a seek or
+	/// fails, in addition to releasing the notes and sustain pedals still held.
+	pub fn set_release(&mut self, release: Release) {
+		self.release = release;
+	}
+
+	/// Sets the region played repeatedly by [Player::play],
+	/// [Player::play_from] and [Player::play_song]; `None` plays tracks once.
+	///
+	/// At the end of the region, the notes still held are released (without
+	/// the messages set with [Player::set_release]), the tempo and channel
+	/// state at the start of the region are restored and playback jumps back.
+	/// The timer is not reset, so no drift builds up over repetitions. Seeking
+	/// past the end of the region leaves the loop.
+	pub fn set_loop(&mut self, region: Option<Loop>) {
+		self.region = region.filter(Loop::is_valid);
+	}
+
+	/// Makes the player a MIDI clock master, so that drum machines, sequencers
+	/// and DAWs can follow playback; `None` turns it off.
+	///
+	/// While playing, the player sends 24 Timing Clock messages per beat,
+	/// following the tempo and speed, through [Connection::send_sys_rt]. It
+	/// sends Start when playback starts at tick 0 and Stop when it ends or is
+	/// paused. When it starts elsewhere, resumes, seeks or jumps back to the
+	/// start of a [Loop], it sends a Song Position Pointer through
+	/// [Connection::send_sys_common] (after Stop, if it was playing), then
+	/// Continue.
+	///
+	/// # Arguments
+	/// - `ticks_per_beat`: Obtained from a [Header](midly::Header), same value
+	///   used for constructing a [Ticker](crate::timers::Ticker).
+	///
+	/// # Notes
+	/// Song Position Pointers count sixteenth notes, so after a seek the
+	/// clock resumes at the next sixteenth note. If `ticks_per_beat` is not a
+	/// multiple of 24, clocks are rounded up to the next tick.
+	pub fn set_clock(&mut self, ticks_per_beat: Option<u16>) {
+		self.clock = ticks_per_beat.map(BeatClock::new);
 	}
 
 	/// Changes `self.timer`, returning the old one.
@@ -35,33 +129,522 @@ impl<T: Timer, C: Connection> Player<T, C> {
 	/// The tempo change events are handled by `self.timer` and playing sound by
 	/// `self.con`.
 	///
-	/// Stops playing if [Connection::play] returns `false`.
-	/// Returns `true` if the track is played through the end, `false` otherwise.
-	pub fn play(&mut self, sheet: &[Moment]) -> bool {
-		let mut counter = 0_u32;
-
-		for moment in sheet {
-			if !moment.is_empty() {
-				self.timer.sleep(counter);
-				counter = 0;
-
-				for event in &moment.events {
-					match event {
-						Event::Tempo(val) => self.timer.change_tempo(*val),
-						Event::Midi(msg) => {
-							if !self.con.play(*msg) {
-								return false;
-							}
+	/// Playback starts at tick 0, so silence before the first moment is
+	/// played too. Moments keep their [tick](Moment::tick) in a slice, so
+	/// `&sheet[n..]` waits until tick `n` before playing anything; to start
+	/// in the middle of a track right away, with the right instruments and
+	/// tempo, use [Player::play_from] instead.
+	///
+	/// Returns a [Playback] describing why and where playback ended: either
+	/// the track was played through the end, or it was stopped with a
+	/// [PlayerHandle].
+	///
+	/// # Errors
+	/// Stops playing and returns an error if the [Connection] fails.
+	pub fn play(&mut self, sheet: &[Moment]) -> Result<Playback, PlayError> {
+		self.timer.reset();
+		self.run(sheet, None, 0, false, None, Queue::Alone)
+	}
+
+	/// Plays the given [Moment] slice, starting at `tick`.
+	///
+	/// The state at `tick` is "chased" first: the last tempo before `tick` is
+	/// given to the timer, and the SysEx messages, program changes, bank
+	/// selects, controllers and pitch bends of the skipped moments are sent
+	/// (see [ChaseState]). Notes that started before `tick` are not played.
+	///
+	/// To start at a point in time, convert it to a tick with
+	/// [TempoMap::tick_at](crate::TempoMap::tick_at).
+	///
+	/// Returns the same as [Player::play].
+	pub fn play_from(&mut self, sheet: &[Moment], tick: u32) -> Result<Playback, PlayError> {
+		self.timer.reset();
+		self.run(sheet, None, tick, true, None, Queue::Alone)
+	}
+
+	/// Plays every track of `song`, combined as in [Song::sheet].
+	///
+	/// Unlike playing the result of [Song::sheet], the player knows which
+	/// track every event comes from, so tracks can be muted and soloed with
+	/// a [PlayerHandle].
+	///
+	/// Returns the same as [Player::play].
+	pub fn play_song(&mut self, song: &Song) -> Result<Playback, PlayError> {
+		let (sheet, tracks) = song.combine();
+		self.timer.reset();
+		self.run(&sheet, Some(&tracks), 0, true, None, Queue::Alone)
+	}
+
+	// Plays `sheet` once, from tick 0 to its end, without resetting the timer,
+	// so that it follows the previous track seamlessly; `first` if there is
+	// no previous track.
+	// The tempo starts at 120 BPM, the default of MIDI files. The loop region
+	// is ignored. Clock followers are only sent Start for the first track and
+	// are not stopped when a track finishes, see `Queue`.
+	pub(crate) fn play_next(&mut self, sheet: &Sheet, first: bool) -> Result<Playback, PlayError> {
+		let end = sheet.len.saturating_sub(1);
+		self.timer.change_tempo(500_000);
+		let region = self.region.take();
+		let queue = if first { Queue::First } else { Queue::Next };
+		let res = self.run(sheet, None, 0, false, Some(end), queue);
+		self.region = region;
+		res
+	}
+
+	// Sends the state of every moment before `tick`, leaving out what the mix
+	// silences.
+	fn chase(
+		&mut self,
+		sheet: &[Moment],
+		tracks: Option<&[Vec<usize>]>,
+		tick: u32,
+	) -> Result<(), ConnectionError> {
+		let i = sheet.partition_point(|m| m.tick() < tick);
+		let state = ChaseState::from_mix(&sheet[..i], tracks, &self.mix);
+		if let Some(tempo) = state.tempo() {
+			self.timer.change_tempo(tempo);
+		}
+
+		for e in state.events() {
+			self.send(&e, None)?;
+		}
+		Ok(())
+	}
+
+	fn run(
+		&mut self,
+		sheet: &[Moment],
+		tracks: Option<&[Vec<usize>]>,
+		start: u32,
+		chase: bool,
+		end: Option<u32>,
+		queue: Queue,
+	) -> Result<Playback, PlayError> {
+		let started = Instant::now();
+		self.shared.set_running(true);
+		self.shared.set_position(start);
+		if let Some(mix) = self.shared.take_mix() {
+			self.mix = mix;
+		}
+
+		let mut res = Ok(());
+		if chase {
+			res = self.chase(sheet, tracks, start);
+		}
+		let res = res
+			.and_then(|_| self.start_clock(start, queue))
+			.and_then(|_| self.run_inner(sheet, tracks, start, end))
+			.and_then(|end| match (end, queue) {
+				(PlaybackEnd::Finished, Queue::First | Queue::Next) => Ok(end),
+				_ => self.send_clock(SystemRealtime::Stop).map(|_| end),
+			});
+		if res.is_err() {
+			// Try not to leave notes hanging; the first error is the one
+			// that matters.
+			let _ = self.release_notes(true);
+			let _ = self.send_clock(SystemRealtime::Stop);
+		}
+		self.shared.set_running(false);
+
+		let tick = self.shared.position();
+		let elapsed = started.elapsed();
+		match res {
+			Ok(end) => Ok(Playback { end, tick, elapsed }),
+			Err(error) => Err(PlayError {
+				error,
+				tick,
+				elapsed,
+			}),
+		}
+	}
+
+	fn run_inner(
+		&mut self,
+		sheet: &[Moment],
+		tracks: Option<&[Vec<usize>]>,
+		start: u32,
+		end: Option<u32>,
+	) -> Result<PlaybackEnd, ConnectionError> {
+		let mut last_tick = start;
+		let mut i = sheet.partition_point(|m| m.tick() < start);
+		// The number of times the loop region was entered, and the state at
+		// its start along with the mix it was chased with.
+		let mut passes = 1;
+		let mut loop_state: Option<(Mix, ChaseState)> = None;
+
+		loop {
+			let next = sheet.get(i).map(|m| m.tick());
+			let jump = self.region.filter(|r| {
+				last_tick < r.end
+					&& next.is_none_or(|t| t >= r.end)
+					&& r.count.is_none_or(|n| passes < n)
+			});
+			let target = match (jump, next, end) {
+				(Some(r), ..) => r.end,
+				(None, Some(t), _) => t,
+				// Wait for the end of the track.
+				(None, None, Some(e)) if e > last_tick => e,
+				_ => break,
+			};
+			// A clock due at the same tick as an event goes first.
+			let clock = self.clock.map(|c| c.next_tick()).filter(|&t| t <= target);
+			let target = clock.unwrap_or(target);
+
+			if let Some(speed) = self.shared.take_speed() {
+				self.timer.set_speed(speed);
+			}
+
+			match self.wait(target - last_tick, &sheet[..i], tracks)? {
+				None => (),
+				Some(Interrupt::Seek(tick)) => {
+					self.release_notes(true)?;
+					self.timer.reset();
+					self.shared.set_position(tick);
+					self.chase(sheet, tracks, tick)?;
+					self.locate_clock(tick, true)?;
+					last_tick = tick;
+					i = sheet.partition_point(|m| m.tick() < tick);
+					continue;
+				}
+				Some(_) => {
+					self.release_notes(true)?;
+					return Ok(PlaybackEnd::Stopped);
+				}
+			}
+
+			if let Some(c) = &mut self.clock {
+				if clock.is_some() {
+					c.advance();
+					self.con.send_sys_rt(SystemRealtime::TimingClock)?;
+					last_tick = target;
+					continue;
+				}
+			}
+
+			if let Some(r) = jump {
+				self.release_notes(false)?;
+				i = sheet.partition_point(|m| m.tick() < r.start);
+				if loop_state.as_ref().is_none_or(|(mix, _)| *mix != self.mix) {
+					let state = ChaseState::from_mix(&sheet[..i], tracks, &self.mix);
+					loop_state = Some((self.mix.clone(), state));
+				}
+				let (_, state) = loop_state.as_ref().unwrap();
+				if let Some(tempo) = state.tempo() {
+					self.timer.change_tempo(tempo);
+				}
+				for ch in 0..16_u8 {
+					for event in state.channel_events(ch.into()) {
+						self.con.play(event)?;
+						self.active.update(&event);
+					}
+				}
+				last_tick = r.start;
+				self.shared.set_position(last_tick);
+				self.locate_clock(last_tick, true)?;
+				passes += 1;
+				continue;
+			}
+
+			let Some(moment) = sheet.get(i) else {
+				last_tick = target;
+				self.shared.set_position(last_tick);
+				continue;
+			};
+			last_tick = moment.tick();
+			self.shared.set_position(last_tick);
+			for (j, event) in moment.events.iter().enumerate() {
+				self.send(event, tracks.map(|t| t[i][j]))?;
+			}
+			i += 1;
+		}
+
+		self.release_notes(false)?;
+		Ok(PlaybackEnd::Finished)
+	}
+
+	// Sleeps for `n_ticks`, staying paused as long as requested.
+	//
+	// Returns early with `Interrupt::Stop` or `Interrupt::Seek`. Changes to
+	// muting and soloing are applied on the way; `played` is what was played
+	// so far, for chasing channels that can be heard again, and `tracks` the
+	// track of its events.
+	fn wait(
+		&mut self,
+		n_ticks: u32,
+		played: &[Moment],
+		tracks: Option<&[Vec<usize>]>,
+	) -> Result<Option<Interrupt>, ConnectionError> {
+		if !self.shared.is_controlled() {
+			// Nobody can send commands, let the timer sleep the way it wants.
+			// There might still be a command sent before the last handle was
+			// dropped.
+			let mut interrupt = self.shared.wait(Duration::ZERO);
+			if let Some(Interrupt::Mix(_)) = interrupt {
+				self.output().apply_mix(played, tracks)?;
+				interrupt = self.shared.wait(Duration::ZERO);
+			}
+			if interrupt.is_none() {
+				self.timer.sleep(n_ticks);
+			}
+			return Ok(interrupt);
+		}
+
+		let Self {
+			con,
+			timer,
+			shared,
+			active,
+			release,
+			mix,
+			clock,
+			..
+		} = self;
+		let shared = &**shared;
+		let mut out = Output {
+			con,
+			shared,
+			active,
+			mix,
+			release: *release,
+			clock: clock.is_some(),
+		};
+		let mut res = Ok(None);
+		let mut paused = false;
+
+		// The timer decides how long to wait, the player handles the commands
+		// received in the meantime.
+		timer.sleep_with(n_ticks, &mut |mut t| loop {
+			match shared.wait(t) {
+				None => return true,
+				Some(Interrupt::Pause(remaining)) => {
+					paused = true;
+					match out.pause(played, tracks) {
+						// Sleep for what's left of the interrupted wait.
+						Ok(None) => t = remaining,
+						other => {
+							res = other;
+							return false;
 						}
-						_ => (),
-					};
+					}
+				}
+				Some(Interrupt::Mix(remaining)) => {
+					if let Err(e) = out.apply_mix(played, tracks) {
+						res = Err(e);
+						return false;
+					}
+					t = remaining;
+				}
+				other => {
+					res = Ok(other);
+					return false;
 				}
 			}
+		});
+
+		if paused {
+			self.timer.reset();
+		}
+		res
+	}
+
+	fn output(&mut self) -> Output<'_, C> {
+		Output {
+			con: &mut self.con,
+			shared: &self.shared,
+			active: &mut self.active,
+			mix: &mut self.mix,
+			release: self.release,
+			clock: self.clock.is_some(),
+		}
+	}
+
+	// Releases the notes still held; with `interrupted`, also sends what
+	// `self.release` asks for.
+	fn release_notes(&mut self, interrupted: bool) -> Result<(), ConnectionError> {
+		self.output().release_notes(interrupted)
+	}
+
+	// Sends the messages `release` asks for on every channel.
+	fn send_release(&mut self, release: Release) -> Result<(), ConnectionError> {
+		self.output().send_release(release)
+	}
+
+	// Sends `msg` if the player is a clock master.
+	fn send_clock(&mut self, msg: SystemRealtime) -> Result<(), ConnectionError> {
+		self.output().send_clock(msg)
+	}
+
+	// Tells clock followers that playback starts at `tick`; a track that
+	// follows another one in a queue keeps them running as they are.
+	fn start_clock(&mut self, tick: u32, queue: Queue) -> Result<(), ConnectionError> {
+		match &mut self.clock {
+			Some(clock) if queue == Queue::Next => {
+				clock.locate(tick);
+				Ok(())
+			}
+			Some(clock) if tick == 0 => {
+				clock.locate(0);
+				self.con.send_sys_rt(SystemRealtime::Start)
+			}
+			_ => self.locate_clock(tick, false),
+		}
+	}
+
+	// Tells clock followers to continue from `tick`; `playing` if they have to
+	// be stopped first.
+	fn locate_clock(&mut self, tick: u32, playing: bool) -> Result<(), ConnectionError> {
+		let Some(clock) = &mut self.clock else {
+			return Ok(());
+		};
+		let pos = clock.locate(tick);
+		if playing {
+			self.con.send_sys_rt(SystemRealtime::Stop)?;
+		}
+		self.con.send_sys_common(SystemCommon::SongPosition(pos))?;
+		self.con.send_sys_rt(SystemRealtime::Continue)
+	}
+
+	// Sends or handles a single event; `track` is the index of the track it
+	// comes from, if known.
+	fn send(&mut self, event: &Event, track: Option<usize>) -> Result<(), ConnectionError> {
+		match event {
+			Event::Tempo(val) => self.timer.change_tempo(*val),
+			Event::Midi(msg) if !self.mix.is_audible(msg.channel, track) => (),
+			Event::Midi(msg) => {
+				self.con.play(*msg)?;
+				self.active.update_track(msg, track);
+			}
+			Event::SysEx(data) => self.con.send_sysex(data)?,
+			Event::Escape(data) => self.con.send_raw(data)?,
+			_ => (),
+		};
+		Ok(())
+	}
+}
+
+// Where a run stands among tracks played back to back, as in a `Playlist`,
+// for clock followers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Queue {
+	// A track played on its own: followers are started, then stopped.
+	Alone,
+	// The first track of a queue: followers are started, and left running
+	// if it finishes.
+	First,
+	// A track following another one: followers are left running.
+	Next,
+}
+
+// The parts of a player that send events, borrowed apart from its timer so
+// that they can be used while the timer sleeps.
+struct Output<'a, C: Connection> {
+	con: &'a mut C,
+	shared: &'a Shared,
+	active: &'a mut ActiveNotes,
+	mix: &'a mut Mix,
+	release: Release,
+	clock: bool,
+}
+
+impl<C: Connection> Output<'_, C> {
+	fn release_notes(&mut self, interrupted: bool) -> Result<(), ConnectionError> {
+		for event in self.active.release() {
+			self.con.play(event)?;
+		}
+		if interrupted {
+			self.send_release(self.release)?;
+		}
+		Ok(())
+	}
+
+	fn send_release(&mut self, release: Release) -> Result<(), ConnectionError> {
+		for message in release.messages() {
+			for ch in 0..16 {
+				self.con.play(MidiEvent {
+					channel: ch.into(),
+					message,
+				})?;
+			}
+		}
+		Ok(())
+	}
+
+	fn send_clock(&mut self, msg: SystemRealtime) -> Result<(), ConnectionError> {
+		if self.clock {
+			self.con.send_sys_rt(msg)
+		} else {
+			Ok(())
+		}
+	}
+
+	// Stays paused until resumed, returning the command that ended the pause
+	// if it was not a resume. Controllers reset on pause are restored from
+	// `played` on resume.
+	fn pause(
+		&mut self,
+		played: &[Moment],
+		tracks: Option<&[Vec<usize>]>,
+	) -> Result<Option<Interrupt>, ConnectionError> {
+		self.release_notes(true)?;
+		self.send_clock(SystemRealtime::Stop)?;
+		if let Some(interrupt) = self.shared.wait_paused() {
+			return Ok(Some(interrupt));
+		}
+		if self.release.reset_controllers {
+			let state = ChaseState::from_mix(played, tracks, self.mix);
+			for ch in 0..16_u8 {
+				self.send_channel_state(&state, ch.into())?;
+			}
+		}
+		self.send_clock(SystemRealtime::Continue)?;
+		Ok(None)
+	}
 
-			counter += 1;
+	// Sends the state of `channel` saved in `state`.
+	fn send_channel_state(
+		&mut self,
+		state: &ChaseState,
+		channel: u4,
+	) -> Result<(), ConnectionError> {
+		for event in state.channel_events(channel) {
+			self.con.play(event)?;
+			self.active.update(&event);
 		}
+		Ok(())
+	}
+
+	// Releases the notes started by tracks and channels that were silenced,
+	// and chases the channels that can be heard again.
+	fn apply_mix(
+		&mut self,
+		played: &[Moment],
+		tracks: Option<&[Vec<usize>]>,
+	) -> Result<(), ConnectionError> {
+		let Some(mix) = self.shared.take_mix() else {
+			return Ok(());
+		};
+		let old = std::mem::replace(self.mix, mix);
 
-		true
+		let mut state = None;
+		for ch in 0..16_u8 {
+			let channel = u4::from(ch);
+			let (silenced, unmuted) = old.changes(self.mix, channel);
+			if silenced {
+				let mix = &*self.mix;
+				let released = self
+					.active
+					.release_tracks(channel, |t| !mix.is_audible(channel, t));
+				for event in released {
+					self.con.play(event)?;
+				}
+			}
+			if unmuted && self.mix.is_channel_audible(channel) {
+				let state =
+					state.get_or_insert_with(|| ChaseState::from_mix(played, tracks, self.mix));
+				self.send_channel_state(state, channel)?;
+			}
+		}
+		Ok(())
 	}
 }
 
@@ -72,23 +655,56 @@ impl<T: Timer, C: Connection> Player<T, C> {
 pub trait Connection {
 	/// Given a [MidiEvent], plays the message.
 	///
-	/// If this function returns `false`, [Player::play] will stop playing and return.
-	fn play(&mut self, event: MidiEvent) -> bool;
+	/// If this function returns an error, [Player::play] will stop playing and
+	/// return it.
+	fn play(&mut self, event: MidiEvent) -> Result<(), ConnectionError>;
+
+	/// Sends a System Exclusive message.
+	///
+	/// `data` does not include the leading `0xF0` byte, just like
+	/// [Event::SysEx]. If it does not end with `0xF7`, it is the first packet
+	/// of a split message; the following packets are sent with
+	/// [Connection::send_raw].
+	///
+	/// If this function returns an error, [Player::play] will stop playing and
+	/// return it.
+	///
+	/// The default implementation of this method does nothing.
+	fn send_sysex(&mut self, _data: &[u8]) -> Result<(), ConnectionError> {
+		Ok(())
+	}
+
+	/// Sends bytes to the device as they are, from an [Event::Escape]: the
+	/// continuation packets of a split SysEx message, or any other data.
+	///
+	/// If this function returns an error, [Player::play] will stop playing and
+	/// return it.
+	///
+	/// The default implementation of this method does nothing.
+	fn send_raw(&mut self, _data: &[u8]) -> Result<(), ConnectionError> {
+		Ok(())
+	}
 
 	/// Sends a system realtime message.
 	///
 	/// The default implementation of this method does nothing.
-	fn send_sys_rt(&mut self, _msg: SystemRealtime) {}
+	fn send_sys_rt(&mut self, _msg: SystemRealtime) -> Result<(), ConnectionError> {
+		Ok(())
+	}
 
 	/// Sends a system common message.
 	///
 	/// The default implementation of this method does nothing.
-	fn send_sys_common(&mut self, _msg: SystemCommon<'_>) {}
+	fn send_sys_common(&mut self, _msg: SystemCommon<'_>) -> Result<(), ConnectionError> {
+		Ok(())
+	}
 
 	/// Turns all notes off.
 	///
 	/// The provided implementation simply blasts every channel with NoteOff messages for every possible note; `16 * 128 = 2048` messages will be sent.
-	fn all_notes_off(&mut self) {
+	/// [Player] does not call this, it releases only the notes it knows are held, see [ActiveNotes].
+	/// It stops at the first error.
+	fn all_notes_off(&mut self) -> Result<(), ConnectionError> {
 		for ch in 0..16 {
 			for note in 0..=127 {
 				self.play(MidiEvent {
@@ -97,31 +713,53 @@ pub trait Connection {
 						key: note.into(),
 						vel: 127.into(),
 					},
-				});
+				})?;
 			}
 		}
+		Ok(())
 	}
 }
 
 #[cfg(feature = "midir")]
 impl Connection for MidiOutputConnection {
-	fn play(&mut self, msg: MidiEvent) -> bool {
+	fn play(&mut self, msg: MidiEvent) -> Result<(), ConnectionError> {
 		let mut buf = Vec::with_capacity(8);
-		let _ = msg.write(&mut buf);
+		msg.write(&mut buf)
+			.map_err(|e| ConnectionError::Write(Box::new(e)))?;
+
+		self.send(&buf)?;
+		Ok(())
+	}
+
+	fn send_sysex(&mut self, data: &[u8]) -> Result<(), ConnectionError> {
+		let mut buf = Vec::with_capacity(data.len() + 1);
+		buf.push(0xf0);
+		buf.extend_from_slice(data);
+
+		self.send(&buf)?;
+		Ok(())
+	}
 
-		let _ = self.send(&buf);
-		true
+	fn send_raw(&mut self, data: &[u8]) -> Result<(), ConnectionError> {
+		self.send(data)?;
+		Ok(())
 	}
 
-	fn send_sys_rt(&mut self, msg: SystemRealtime) {
+	fn send_sys_rt(&mut self, msg: SystemRealtime) -> Result<(), ConnectionError> {
 		let mut buf = Vec::with_capacity(8);
-		let _ = midly::live::LiveEvent::Realtime(msg).write(&mut buf);
-		let _ = self.send(&buf);
+		midly::live::LiveEvent::Realtime(msg)
+			.write_std(&mut buf)
+			.map_err(|e| ConnectionError::Write(Box::new(e)))?;
+		self.send(&buf)?;
+		Ok(())
 	}
 
-	fn send_sys_common(&mut self, msg: SystemCommon<'_>) {
+	fn send_sys_common(&mut self, msg: SystemCommon<'_>) -> Result<(), ConnectionError> {
 		let mut buf = Vec::with_capacity(8);
-		let _ = midly::live::LiveEvent::Common(msg).write(&mut buf);
-		let _ = self.send(&buf);
+		midly::live::LiveEvent::Common(msg)
+			.write_std(&mut buf)
+			.map_err(|e| ConnectionError::Write(Box::new(e)))?;
+		self.send(&buf)?;
+		Ok(())
 	}
 }
@@ -0,0 +1,271 @@
+use std::collections::BTreeMap;
+
+use midly::{num::u4, MidiMessage};
+
+use crate::MidiEvent;
+
+const SUSTAIN: u8 = 64;
+const ALL_SOUND_OFF: u8 = 120;
+const RESET_CONTROLLERS: u8 = 121;
+const ALL_NOTES_OFF: u8 = 123;
+
+/// Keeps track of the notes and sustain pedals that are currently held down.
+///
+/// Feed every [MidiEvent] sent to a device to [ActiveNotes::update]; at any
+/// point, [ActiveNotes::release] returns the messages that release exactly
+/// what is still held. This is much faster than
+/// [Connection::all_notes_off](crate::Connection::all_notes_off), which sends
+/// 2048 messages.
+///
+/// [Player](crate::Player) does this by itself, see [Player::set_release](crate::Player::set_release).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ActiveNotes {
+	// One bit per key, per channel.
+	notes: [u128; 16],
+	// One bit per channel.
+	sustain: u16,
+	// The track that started each held note, or held the sustain pedal
+	// (key `None`), when known: (channel, key) to track.
+	tracks: BTreeMap<(u8, Option<u8>), usize>,
+}
+
+/// What a [Player](crate::Player) sends when playback is stopped, paused,
+/// moved with a seek or fails.
+///
+/// The held notes and sustain pedals are always released (see
+/// [ActiveNotes]); the fields select the messages sent after that, on all 16
+/// channels.
+///
+/// The default sends nothing extra.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Release {
+	/// Send "All Sound Off" (CC 120), cutting off reverb and release tails.
+	pub all_sound_off: bool,
+	/// Send "All Notes Off" (CC 123).
+	pub all_notes_off: bool,
+	/// Send "Reset All Controllers" (CC 121). When playback resumes after a
+	/// pause, the controllers, programs and pitch bends set before it are sent
+	/// again.
+	pub reset_controllers: bool,
+}
+
+impl ActiveNotes {
+	/// Creates a new [ActiveNotes] with nothing held.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Records the effect of `event`.
+	///
+	/// A NoteOn with a velocity of 0 counts as a NoteOff, and a sustain pedal
+	/// (CC 64) is held at a value of 64 or more. Channel mode messages that
+	/// silence a channel (CC 120, 121 and 123) are honoured.
+	pub fn update(&mut self, event: &MidiEvent) {
+		self.update_track(event, None);
+	}
+
+	// Same as `update`, remembering that `event` comes from `track`.
+	pub(crate) fn update_track(&mut self, event: &MidiEvent, track: Option<usize>) {
+		let ch = event.channel.as_int() as usize;
+		let (key, held) = match event.message {
+			MidiMessage::NoteOn { key, vel } if vel > 0 => {
+				self.notes[ch] |= 1 << key.as_int();
+				(Some(key.as_int()), true)
+			}
+			MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+				self.notes[ch] &= !(1 << key.as_int());
+				(Some(key.as_int()), false)
+			}
+			MidiMessage::Controller { controller, value } => match controller.as_int() {
+				SUSTAIN if value >= 64 => {
+					self.sustain |= 1 << ch;
+					(None, true)
+				}
+				SUSTAIN | RESET_CONTROLLERS => {
+					self.sustain &= !(1 << ch);
+					(None, false)
+				}
+				ALL_SOUND_OFF | ALL_NOTES_OFF => {
+					self.notes[ch] = 0;
+					self.tracks
+						.retain(|&(c, k), _| c != ch as u8 || k.is_none());
+					return;
+				}
+				_ => return,
+			},
+			_ => return,
+		};
+
+		match track {
+			Some(t) if held => self.tracks.insert((ch as u8, key), t),
+			_ => self.tracks.remove(&(ch as u8, key)),
+		};
+	}
+
+	/// Returns `true` if no note or sustain pedal is held.
+	pub fn is_empty(&self) -> bool {
+		self.sustain == 0 && self.notes.iter().all(|&n| n == 0)
+	}
+
+	/// Returns `true` if `key` is held on `channel`.
+	pub fn is_held(&self, channel: u4, key: u8) -> bool {
+		key < 128 && self.notes[channel.as_int() as usize] & (1 << key) != 0
+	}
+
+	/// Forgets everything that is held, without sending anything.
+	pub fn clear(&mut self) {
+		*self = Self::default();
+	}
+
+	/// Returns the messages that release every held note and sustain pedal,
+	/// then forgets them.
+	///
+	/// Every held note gets a NoteOff with a velocity of 64, followed by a
+	/// sustain pedal release on channels that had it held.
+	pub fn release(&mut self) -> Vec<MidiEvent> {
+		(0..16_u8)
+			.flat_map(|ch| self.release_channel(ch.into()))
+			.collect()
+	}
+
+	/// Same as [ActiveNotes::release] but only for `channel`.
+	pub fn release_channel(&mut self, channel: u4) -> Vec<MidiEvent> {
+		self.release_tracks(channel, |_| true)
+	}
+
+	// Same as `release_channel`, but only for what was started by a track
+	// for which `f` returns `true`; `None` is an unknown track.
+	pub(crate) fn release_tracks(
+		&mut self,
+		channel: u4,
+		f: impl Fn(Option<usize>) -> bool,
+	) -> Vec<MidiEvent> {
+		let ch = channel.as_int() as usize;
+		let mut release = |key| {
+			let track = self.tracks.get(&(ch as u8, key)).copied();
+			let released = f(track);
+			if released {
+				self.tracks.remove(&(ch as u8, key));
+			}
+			released
+		};
+
+		let notes = self.notes[ch];
+		let keys = (0..128_u8)
+			.filter(|&k| notes & (1 << k) != 0 && release(Some(k)))
+			.collect::<Vec<_>>();
+		let sustain = self.sustain & (1 << ch) != 0 && release(None);
+
+		let mut buf = Vec::with_capacity(keys.len() + 1);
+		for key in keys {
+			self.notes[ch] &= !(1 << key);
+			buf.push(MidiEvent {
+				channel,
+				message: MidiMessage::NoteOff {
+					key: key.into(),
+					vel: 64.into(),
+				},
+			});
+		}
+		if sustain {
+			self.sustain &= !(1 << ch);
+			buf.push(MidiEvent {
+				channel,
+				message: MidiMessage::Controller {
+					controller: SUSTAIN.into(),
+					value: 0.into(),
+				},
+			});
+		}
+		buf
+	}
+}
+
+impl Release {
+	// Returns the channel mode messages selected.
+	pub(crate) fn messages(&self) -> impl Iterator<Item = MidiMessage> {
+		[
+			(self.all_sound_off, ALL_SOUND_OFF),
+			(self.all_notes_off, ALL_NOTES_OFF),
+			(self.reset_controllers, RESET_CONTROLLERS),
+		]
+		.into_iter()
+		.filter(|(on, _)| *on)
+		.map(|(_, n)| MidiMessage::Controller {
+			controller: n.into(),
+			value: 0.into(),
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn ev(channel: u8, message: MidiMessage) -> MidiEvent {
+		MidiEvent {
+			channel: channel.into(),
+			message,
+		}
+	}
+
+	fn note_on(channel: u8, key: u8, vel: u8) -> MidiEvent {
+		ev(
+			channel,
+			MidiMessage::NoteOn {
+				key: key.into(),
+				vel: vel.into(),
+			},
+		)
+	}
+
+	fn cc(channel: u8, n: u8, value: u8) -> MidiEvent {
+		ev(
+			channel,
+			MidiMessage::Controller {
+				controller: n.into(),
+				value: value.into(),
+			},
+		)
+	}
+
+	#[test]
+	fn release_held() {
+		let mut active = ActiveNotes::new();
+		for e in [
+			note_on(0, 60, 100),
+			note_on(0, 64, 100),
+			note_on(3, 127, 1),
+			cc(3, SUSTAIN, 127),
+			note_on(0, 60, 0),
+			cc(9, SUSTAIN, 127),
+			cc(9, SUSTAIN, 0),
+		] {
+			active.update(&e);
+		}
+
+		assert!(active.is_held(0.into(), 64));
+		assert!(!active.is_held(0.into(), 60));
+		assert_eq!(
+			active.release(),
+			[
+				ev(
+					0,
+					MidiMessage::NoteOff {
+						key: 64.into(),
+						vel: 64.into()
+					}
+				),
+				ev(
+					3,
+					MidiMessage::NoteOff {
+						key: 127.into(),
+						vel: 64.into()
+					}
+				),
+				cc(3, SUSTAIN, 0),
+			]
+		);
+		assert!(active.is_empty());
+	}
+}
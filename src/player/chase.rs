@@ -0,0 +1,404 @@
+use std::collections::BTreeMap;
+
+use midly::{
+	num::{u4, u7},
+	MidiMessage, PitchBend,
+};
+
+use super::mix::Mix;
+use crate::{Event, MidiEvent, Moment};
+
+// Controllers that are not chased as plain values.
+const BANK_MSB: u8 = 0;
+const BANK_LSB: u8 = 32;
+const DATA_MSB: u8 = 6;
+const DATA_LSB: u8 = 38;
+const NRPN_LSB: u8 = 98;
+const NRPN_MSB: u8 = 99;
+const RPN_LSB: u8 = 100;
+const RPN_MSB: u8 = 101;
+const RESET_CONTROLLERS: u8 = 121;
+
+/// Remembers the state of every MIDI channel and the tempo, so that it can be
+/// restored when playback starts in the middle of a track.
+///
+/// Feed every event that is skipped over to [ChaseState::update], then send
+/// the result of [ChaseState::events] before resuming playback. This is what
+/// [Player::play_from](crate::Player::play_from) does.
+///
+/// The following are tracked:
+/// - The last tempo.
+/// - SysEx messages, in order (these often reset or set up a device). A
+///   message split into packets is joined with its
+///   [continuation packets](Event::Escape); one that is never completed is
+///   skipped. A message replaces an earlier one setting the same thing (the
+///   same universal sub-IDs, or the same Roland or Yamaha parameter address,
+///   whatever the device ID), and a GM, GS or XG reset drops every message
+///   before it.
+/// - Per channel: bank select and program change, every controller except the
+///   channel mode messages (120 to 127), values set through RPNs and NRPNs
+///   (such as the pitch bend range), pitch bend and channel pressure. "Reset
+///   all controllers" (121) is honoured and replayed before the controllers
+///   set after it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChaseState {
+	tempo: Option<u32>,
+	// Complete SysEx messages in order, along with their kind.
+	sysex: Vec<(Vec<u8>, Vec<u8>)>,
+	// A SysEx message still waiting for continuation packets.
+	partial: Option<Vec<u8>>,
+	channels: [ChannelState; 16],
+}
+
+// An (N)RPN: (is_nrpn, msb, lsb).
+type Param = (bool, u8, u8);
+// Data entry values: (msb, lsb).
+type ParamValue = (Option<u7>, Option<u7>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ChannelState {
+	// Whether a "reset all controllers" message was seen.
+	reset: bool,
+	program: Option<u7>,
+	controllers: [Option<u7>; 120],
+	// The currently selected (N)RPN; `true` for NRPN.
+	param: (bool, Option<u7>, Option<u7>),
+	params: BTreeMap<Param, ParamValue>,
+	pitch_bend: Option<PitchBend>,
+	pressure: Option<u7>,
+}
+
+impl Default for ChannelState {
+	fn default() -> Self {
+		Self {
+			reset: false,
+			program: None,
+			controllers: [None; 120],
+			param: (false, None, None),
+			params: BTreeMap::new(),
+			pitch_bend: None,
+			pressure: None,
+		}
+	}
+}
+
+impl ChaseState {
+	/// Creates a new, blank [ChaseState].
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Creates a [ChaseState] from every event in `moments`.
+	pub fn from_moments(moments: &[Moment]) -> Self {
+		let mut s = Self::new();
+		for m in moments {
+			for e in &m.events {
+				s.update(e);
+			}
+		}
+		s
+	}
+
+	// Same as `from_moments`, leaving out the MIDI events that `mix` silences;
+	// `tracks` holds the track of every event, as returned by `Song::combine`.
+	pub(crate) fn from_mix(moments: &[Moment], tracks: Option<&[Vec<usize>]>, mix: &Mix) -> Self {
+		let mut s = Self::new();
+		for (i, m) in moments.iter().enumerate() {
+			for (j, e) in m.events.iter().enumerate() {
+				match e {
+					Event::Midi(msg) if !mix.is_audible(msg.channel, tracks.map(|t| t[i][j])) => (),
+					_ => s.update(e),
+				}
+			}
+		}
+		s
+	}
+
+	/// Records the effect of `event`.
+	pub fn update(&mut self, event: &Event) {
+		match event {
+			Event::Tempo(t) => self.tempo = Some(*t),
+			Event::SysEx(data) => {
+				// An unfinished message before this one is dropped.
+				self.partial = None;
+				self.sysex_packet(data.clone());
+			}
+			Event::Escape(data) => {
+				if let Some(mut buf) = self.partial.take() {
+					buf.extend_from_slice(data);
+					self.sysex_packet(buf);
+				}
+			}
+			Event::Midi(MidiEvent { channel, message }) => {
+				self.channels[channel.as_int() as usize].update(message)
+			}
+			_ => (),
+		}
+	}
+
+	fn sysex_packet(&mut self, data: Vec<u8>) {
+		if data.last() != Some(&0xf7) {
+			self.partial = Some(data);
+			return;
+		}
+		let kind = sysex_kind(&data);
+		if is_reset(&kind) {
+			self.sysex.clear();
+		}
+		self.sysex.retain(|(k, _)| *k != kind);
+		self.sysex.push((kind, data));
+	}
+
+	/// Returns the last tempo seen, if any.
+	pub fn tempo(&self) -> Option<u32> {
+		self.tempo
+	}
+
+	/// Returns the events that restore the recorded state.
+	///
+	/// SysEx messages come first, followed by the MIDI messages of every
+	/// channel. The tempo is not included, see [ChaseState::tempo].
+	pub fn events(&self) -> Vec<Event> {
+		let mut buf = self
+			.sysex
+			.iter()
+			.map(|(_, data)| Event::SysEx(data.clone()))
+			.collect::<Vec<_>>();
+
+		for ch in 0..16 {
+			buf.extend(self.channel_events(ch.into()).into_iter().map(Event::Midi));
+		}
+
+		buf
+	}
+
+	/// Returns the MIDI messages that restore the recorded state of a single
+	/// channel.
+	pub fn channel_events(&self, channel: u4) -> Vec<MidiEvent> {
+		self.channels[channel.as_int() as usize]
+			.messages()
+			.into_iter()
+			.map(|message| MidiEvent { channel, message })
+			.collect()
+	}
+}
+
+// The part of a SysEx message telling what it sets, without the device ID;
+// a message replaces an earlier one of the same kind.
+fn sysex_kind(data: &[u8]) -> Vec<u8> {
+	let [id, device, rest @ ..] = data else {
+		return data.to_vec();
+	};
+	let len = match (id, rest) {
+		// Universal: sub-IDs.
+		(0x7e | 0x7f, _) => 2,
+		// Roland DT1: model, command and address.
+		(0x41, [_, 0x12, ..]) => 5,
+		// Yamaha parameter change: model and address.
+		(0x43, _) if device & 0xf0 == 0x10 => 4,
+		_ => return data.to_vec(),
+	};
+	let mut kind = vec![*id];
+	kind.extend(rest.iter().take(len));
+	kind
+}
+
+// Whether a SysEx message of this kind resets the device: GM System On or
+// Off, GS Reset or XG System On.
+fn is_reset(kind: &[u8]) -> bool {
+	matches!(
+		kind,
+		[0x7e, 0x09, _] | [0x41, 0x42, 0x12, 0x40, 0x00, 0x7f] | [0x43, 0x4c, 0x00, 0x00, 0x7e]
+	)
+}
+
+impl ChannelState {
+	fn update(&mut self, msg: &MidiMessage) {
+		match *msg {
+			MidiMessage::ProgramChange { program } => self.program = Some(program),
+			MidiMessage::PitchBend { bend } => self.pitch_bend = Some(bend),
+			MidiMessage::ChannelAftertouch { vel } => self.pressure = Some(vel),
+			MidiMessage::Controller { controller, value } => {
+				match controller.as_int() {
+					RPN_MSB => self.param = (false, Some(value), self.param.2),
+					RPN_LSB => self.param = (false, self.param.1, Some(value)),
+					NRPN_MSB => self.param = (true, Some(value), self.param.2),
+					NRPN_LSB => self.param = (true, self.param.1, Some(value)),
+					DATA_MSB | DATA_LSB => {
+						if let (nrpn, Some(msb), Some(lsb)) = self.param {
+							// 127/127 is the "null" parameter.
+							if msb != 127 || lsb != 127 {
+								let entry = self
+									.params
+									.entry((nrpn, msb.as_int(), lsb.as_int()))
+									.or_default();
+								if controller == DATA_MSB {
+									entry.0 = Some(value);
+								} else {
+									entry.1 = Some(value);
+								}
+							}
+						}
+					}
+					RESET_CONTROLLERS => {
+						// As described in RP-015.
+						self.reset = true;
+						self.pitch_bend = None;
+						self.pressure = None;
+						self.param = (false, None, None);
+						for n in [1, 11, 64, 65, 66, 67] {
+							self.controllers[n] = None;
+						}
+					}
+					n if n < 120 => self.controllers[n as usize] = Some(value),
+					_ => (),
+				}
+			}
+			_ => (),
+		}
+	}
+
+	fn messages(&self) -> Vec<MidiMessage> {
+		let cc = |n: u8, value: u7| MidiMessage::Controller {
+			controller: n.into(),
+			value,
+		};
+		let mut buf = Vec::new();
+
+		if self.reset {
+			buf.push(cc(RESET_CONTROLLERS, 0.into()));
+		}
+		for n in [BANK_MSB, BANK_LSB] {
+			if let Some(value) = self.controllers[n as usize] {
+				buf.push(cc(n, value));
+			}
+		}
+		if let Some(program) = self.program {
+			buf.push(MidiMessage::ProgramChange { program });
+		}
+
+		for (n, value) in self.controllers.iter().enumerate() {
+			let n = n as u8;
+			if matches!(n, BANK_MSB | BANK_LSB | DATA_MSB | DATA_LSB | 96..=RPN_MSB) {
+				continue;
+			}
+			if let Some(value) = *value {
+				buf.push(cc(n, value));
+			}
+		}
+
+		for (&(nrpn, msb, lsb), &(data_msb, data_lsb)) in &self.params {
+			let (n_msb, n_lsb) = if nrpn {
+				(NRPN_MSB, NRPN_LSB)
+			} else {
+				(RPN_MSB, RPN_LSB)
+			};
+			buf.push(cc(n_msb, msb.into()));
+			buf.push(cc(n_lsb, lsb.into()));
+			buf.extend(data_msb.map(|v| cc(DATA_MSB, v)));
+			buf.extend(data_lsb.map(|v| cc(DATA_LSB, v)));
+		}
+		if !self.params.is_empty() {
+			buf.push(cc(RPN_MSB, 127.into()));
+			buf.push(cc(RPN_LSB, 127.into()));
+		}
+
+		if let Some(bend) = self.pitch_bend {
+			buf.push(MidiMessage::PitchBend { bend });
+		}
+		if let Some(vel) = self.pressure {
+			buf.push(MidiMessage::ChannelAftertouch { vel });
+		}
+
+		buf
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn cc(n: u8, value: u8) -> MidiMessage {
+		MidiMessage::Controller {
+			controller: n.into(),
+			value: value.into(),
+		}
+	}
+
+	#[test]
+	fn chase_channel() {
+		let ch = u4::new(2);
+		let mut state = ChaseState::new();
+		state.update(&Event::Tempo(400_000));
+		state.update(&Event::SysEx(vec![0x7e, 0xf7]));
+		for msg in [
+			MidiMessage::ProgramChange { program: 10.into() },
+			cc(7, 100),
+			cc(0, 1),
+			MidiMessage::ProgramChange { program: 20.into() },
+			cc(RPN_MSB, 0),
+			cc(RPN_LSB, 0),
+			cc(DATA_MSB, 12),
+			cc(7, 90),
+			cc(64, 127),
+			cc(123, 0),
+			MidiMessage::NoteOn {
+				key: 60.into(),
+				vel: 100.into(),
+			},
+		] {
+			state.update(&Event::Midi(MidiEvent {
+				channel: ch,
+				message: msg,
+			}));
+		}
+
+		assert_eq!(state.tempo(), Some(400_000));
+		assert_eq!(
+			state.channel_events(ch),
+			[
+				cc(0, 1),
+				MidiMessage::ProgramChange { program: 20.into() },
+				cc(7, 90),
+				cc(64, 127),
+				cc(RPN_MSB, 0),
+				cc(RPN_LSB, 0),
+				cc(DATA_MSB, 12),
+				cc(RPN_MSB, 127),
+				cc(RPN_LSB, 127),
+			]
+			.map(|message| MidiEvent {
+				channel: ch,
+				message
+			})
+		);
+		assert_eq!(state.events()[0], Event::SysEx(vec![0x7e, 0xf7]));
+		assert!(state.channel_events(0.into()).is_empty());
+	}
+
+	#[test]
+	fn chase_sysex() {
+		let gs_reset = [0x41, 0x10, 0x42, 0x12, 0x40, 0x00, 0x7f, 0x00, 0x41, 0xf7];
+		let volume = |v: u8| vec![0x7f, 0x7f, 0x04, 0x01, 0x00, v, 0xf7];
+		let mut state = ChaseState::new();
+		for e in [
+			Event::SysEx(volume(10)),
+			Event::SysEx(gs_reset[..4].to_vec()),
+			Event::Escape(gs_reset[4..8].to_vec()),
+			Event::Escape(gs_reset[8..].to_vec()),
+			Event::SysEx(volume(20)),
+			// Never completed.
+			Event::SysEx(vec![0x43, 0x10, 0x4c]),
+			Event::SysEx(volume(30)),
+		] {
+			state.update(&e);
+		}
+
+		// The volume before the reset is dropped, the last one replaces the
+		// others.
+		assert_eq!(
+			state.events(),
+			[Event::SysEx(gs_reset.to_vec()), Event::SysEx(volume(30))]
+		);
+	}
+}
@@ -0,0 +1,149 @@
+use midly::num::u14;
+
+// Timing Clock messages per beat.
+pub(crate) const PPQN: u64 = 24;
+// Timing Clock messages per MIDI beat (sixteenth note), the unit of the Song
+// Position Pointer.
+pub(crate) const CLOCKS_PER_SPP: u64 = 6;
+
+// Schedules the MIDI beat clock of a player acting as clock master.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct BeatClock {
+	ticks_per_beat: u64,
+	// The index of the next Timing Clock, counted from tick 0.
+	next: u64,
+}
+
+impl BeatClock {
+	pub(crate) fn new(ticks_per_beat: u16) -> Self {
+		Self {
+			ticks_per_beat: ticks_per_beat.max(1) as u64,
+			next: 0,
+		}
+	}
+
+	// The tick the next Timing Clock is due at; clocks that fall between two
+	// ticks are sent at the later one.
+	pub(crate) fn next_tick(&self) -> u32 {
+		let t = (self.next * self.ticks_per_beat).div_ceil(PPQN);
+		u32::try_from(t).unwrap_or(u32::MAX)
+	}
+
+	pub(crate) fn advance(&mut self) {
+		self.next += 1;
+	}
+
+	// Moves to the first sixteenth note at or after `tick`, returning its Song
+	// Position Pointer; the next Timing Clock falls on it.
+	pub(crate) fn locate(&mut self, tick: u32) -> u14 {
+		let pos = (tick as u64 * PPQN / CLOCKS_PER_SPP)
+			.div_ceil(self.ticks_per_beat)
+			.min(u14::max_value().as_int() as u64);
+		self.next = pos * CLOCKS_PER_SPP;
+		u14::new(pos as u16)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use midly::live::{SystemCommon, SystemRealtime};
+
+	use super::*;
+	use crate::{timers::Ticker, Connection, ConnectionError, Event, MidiEvent, Moment, Player};
+
+	#[derive(Debug, PartialEq)]
+	enum Sent {
+		Note,
+		Rt(SystemRealtime),
+		Spp(u16),
+	}
+
+	impl Connection for Vec<Sent> {
+		fn play(&mut self, _: MidiEvent) -> Result<(), ConnectionError> {
+			self.push(Sent::Note);
+			Ok(())
+		}
+
+		fn send_sys_rt(&mut self, msg: SystemRealtime) -> Result<(), ConnectionError> {
+			self.push(Sent::Rt(msg));
+			Ok(())
+		}
+
+		fn send_sys_common(&mut self, msg: SystemCommon<'_>) -> Result<(), ConnectionError> {
+			if let SystemCommon::SongPosition(pos) = msg {
+				self.push(Sent::Spp(pos.as_int()));
+			}
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn beat_clock() {
+		let mut clock = BeatClock::new(96);
+		let ticks = (0..6)
+			.map(|_| {
+				let t = clock.next_tick();
+				clock.advance();
+				t
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(ticks, [0, 4, 8, 12, 16, 20]);
+
+		// A sixteenth is 24 ticks.
+		assert_eq!(clock.locate(24), 1);
+		assert_eq!(clock.next_tick(), 24);
+		assert_eq!(clock.locate(25), 2);
+		assert_eq!(clock.next_tick(), 48);
+
+		// 100 ticks per beat: a clock every 4.17 ticks.
+		let mut clock = BeatClock::new(100);
+		clock.advance();
+		assert_eq!(clock.next_tick(), 5);
+		assert_eq!(clock.locate(400), 16);
+	}
+
+	#[test]
+	fn clock_master() {
+		let note = |tick| {
+			Moment::with_events(
+				tick,
+				vec![Event::Midi(MidiEvent {
+					channel: 0.into(),
+					message: midly::MidiMessage::NoteOn {
+						key: 60.into(),
+						vel: 100.into(),
+					},
+				})],
+			)
+		};
+		let sheet = [note(0), note(48)];
+		// At 120 BPM, the test takes a quarter of a second.
+		let mut player = Player::new(Ticker::new(96), Vec::new());
+		player.set_clock(Some(96));
+
+		player.play(&sheet).unwrap();
+		let clocks = |n| (0..n).map(|_| Sent::Rt(SystemRealtime::TimingClock));
+		let mut expected = vec![Sent::Rt(SystemRealtime::Start)];
+		expected.extend(clocks(1));
+		expected.push(Sent::Note);
+		expected.extend(clocks(12));
+		expected.push(Sent::Note);
+		// Stop, after the note is released.
+		expected.extend([Sent::Note, Sent::Rt(SystemRealtime::Stop)]);
+		assert_eq!(player.con, expected);
+
+		player.con.clear();
+		player.play_from(&sheet, 30).unwrap();
+		assert_eq!(
+			player.con,
+			[
+				Sent::Spp(2),
+				Sent::Rt(SystemRealtime::Continue),
+				Sent::Rt(SystemRealtime::TimingClock),
+				Sent::Note,
+				Sent::Note,
+				Sent::Rt(SystemRealtime::Stop),
+			]
+		);
+	}
+}
@@ -0,0 +1,169 @@
+use std::{error::Error, fmt, time::Duration};
+
+/// An error that might arise while sending a message through a
+/// [Connection](crate::Connection).
+#[derive(Debug)]
+pub enum ConnectionError {
+	/// The device is not available anymore.
+	///
+	/// This is for [Connection](crate::Connection) implementations that can
+	/// tell a missing device apart; `midir` cannot, so its errors are reported
+	/// as [ConnectionError::Write], see the `From<midir::SendError>`
+	/// implementation.
+	Disconnected,
+	/// The message was rejected as invalid.
+	InvalidData(&'static str),
+	/// The message could not be written to the device.
+	Write(Box<dyn Error + Send + Sync>),
+}
+
+impl Error for ConnectionError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match self {
+			Self::Write(e) => Some(e.as_ref()),
+			_ => None,
+		}
+	}
+}
+
+impl fmt::Display for ConnectionError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Disconnected => f.write_str("the MIDI device is disconnected"),
+			Self::InvalidData(s) => write!(f, "invalid MIDI data: {}", s),
+			Self::Write(e) => write!(f, "failed to write to the MIDI device: {}", e),
+		}
+	}
+}
+
+/// Converts a `midir` error: [SendError::InvalidData](midir::SendError::InvalidData)
+/// becomes [ConnectionError::InvalidData], anything else
+/// [ConnectionError::Write].
+///
+/// `midir` reports a disconnected device the same way as any other failure
+/// to send, so [ConnectionError::Disconnected] is never returned.
+#[cfg(feature = "midir")]
+impl From<midir::SendError> for ConnectionError {
+	fn from(e: midir::SendError) -> Self {
+		match e {
+			midir::SendError::InvalidData(s) => Self::InvalidData(s),
+			e => Self::Write(Box::new(e)),
+		}
+	}
+}
+
+/// Describes why playback ended.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PlaybackEnd {
+	/// Every event was played.
+	Finished,
+	/// Playback was stopped through a [PlayerHandle](crate::PlayerHandle).
+	Stopped,
+}
+
+/// The outcome of a successful [Player::play](crate::Player::play).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Playback {
+	/// Why playback ended.
+	pub end: PlaybackEnd,
+	/// The tick of the last event that was played.
+	pub tick: u32,
+	/// The wall-clock time spent playing, pauses included.
+	pub elapsed: Duration,
+}
+
+impl Playback {
+	/// Returns `true` if the track was played through the end.
+	pub fn is_finished(&self) -> bool {
+		self.end == PlaybackEnd::Finished
+	}
+}
+
+/// The error returned by [Player::play](crate::Player::play) when the
+/// [Connection](crate::Connection) fails.
+#[derive(Debug)]
+pub struct PlayError {
+	/// The error returned by the connection.
+	pub error: ConnectionError,
+	/// The tick playback had reached: the tick of the event that could not be
+	/// sent or, if the connection failed while releasing notes or sending
+	/// MIDI clock, of the last event played.
+	pub tick: u32,
+	/// The wall-clock time spent playing, pauses included.
+	pub elapsed: Duration,
+}
+
+impl Error for PlayError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		Some(&self.error)
+	}
+}
+
+impl fmt::Display for PlayError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "playback failed at tick {}: {}", self.tick, self.error)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use midly::MidiMessage;
+
+	use super::*;
+	use crate::{timers::Ticker, Connection, Event, MidiEvent, Moment, Player};
+
+	// Fails to play key 62, records everything else.
+	#[derive(Default)]
+	struct Flaky(Vec<MidiMessage>);
+
+	impl Connection for Flaky {
+		fn play(&mut self, event: MidiEvent) -> Result<(), ConnectionError> {
+			match event.message {
+				MidiMessage::NoteOn { key, .. } if key == 62 => Err(ConnectionError::Disconnected),
+				msg => {
+					self.0.push(msg);
+					Ok(())
+				}
+			}
+		}
+	}
+
+	fn note_on(key: u8) -> MidiMessage {
+		MidiMessage::NoteOn {
+			key: key.into(),
+			vel: 100.into(),
+		}
+	}
+
+	#[test]
+	fn failing_connection() {
+		let note = |tick, key| {
+			Moment::with_events(
+				tick,
+				vec![Event::Midi(MidiEvent {
+					channel: 0.into(),
+					message: note_on(key),
+				})],
+			)
+		};
+		let sheet = [note(0, 60), note(48, 62), note(96, 64)];
+		// A beat is 200ms long.
+		let mut player = Player::new(Ticker::with_initial_tempo(96, 200_000), Flaky::default());
+
+		let e = player.play(&sheet).unwrap_err();
+		assert!(matches!(e.error, ConnectionError::Disconnected));
+		assert_eq!(e.tick, 48);
+		assert!(e.elapsed >= Duration::from_millis(100));
+		// The held note is released, the last one is never played.
+		assert_eq!(
+			player.con.0,
+			[
+				note_on(60),
+				MidiMessage::NoteOff {
+					key: 60.into(),
+					vel: 64.into(),
+				},
+			]
+		);
+	}
+}
@@ -0,0 +1,503 @@
+use std::{
+	sync::{
+		atomic::{AtomicU32, AtomicUsize, Ordering},
+		Arc, Condvar, Mutex, MutexGuard, Weak,
+	},
+	time::{Duration, Instant},
+};
+
+use midly::num::u4;
+
+use super::mix::Mix;
+use crate::timers::sleep;
+
+// The last part of a wait is slept with `timers::sleep` for precision.
+const SPIN: Duration = Duration::from_millis(3);
+// The range speeds are clamped to.
+const MIN_SPEED: f32 = 0.01;
+const MAX_SPEED: f32 = 100.0;
+
+/// The state of a [Player](crate::Player), as reported by
+/// [PlayerHandle::state].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PlaybackState {
+	/// The player is not playing anything.
+	Stopped,
+	/// The player is playing.
+	Playing,
+	/// The player is in the middle of a track, but paused.
+	Paused,
+}
+
+/// A handle for controlling a [Player](crate::Player) from any thread.
+///
+/// Obtained with [Player::handle](crate::Player::handle). Handles are cheap to
+/// clone and every clone controls the same player.
+///
+/// # Notes
+/// - Commands take effect while the player is in [Player::play](crate::Player::play)
+///   or [Player::play_from](crate::Player::play_from); pausing or seeking
+///   before playback starts is remembered, stopping is not.
+/// - If every handle is dropped while the player is paused, playback stops,
+///   since nothing could resume it anymore.
+/// - Muting and soloing are remembered across playbacks.
+#[derive(Debug)]
+pub struct PlayerHandle {
+	shared: Arc<Shared>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Shared {
+	control: Mutex<Control>,
+	cvar: Condvar,
+	handles: AtomicUsize,
+	position: AtomicU32,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Control {
+	running: bool,
+	paused: bool,
+	stop: bool,
+	seek: Option<u32>,
+	speed: Option<f32>,
+	mix: Mix,
+	mix_changed: bool,
+}
+
+// A reference to a player that does not count as a handle, so it does not
+// keep the player from being stopped once every [PlayerHandle] is dropped
+// while paused.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct WeakHandle(Weak<Shared>);
+
+/// Why a wait on [Shared] ended early.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum Interrupt {
+	Stop,
+	Seek(u32),
+	// The remaining duration of the interrupted wait.
+	Pause(Duration),
+	// Muting or soloing changed; with the remaining duration of the wait.
+	Mix(Duration),
+}
+
+impl PlayerHandle {
+	pub(crate) fn new(shared: Arc<Shared>) -> Self {
+		shared.handles.fetch_add(1, Ordering::SeqCst);
+		Self { shared }
+	}
+
+	pub(crate) fn downgrade(&self) -> WeakHandle {
+		WeakHandle(Arc::downgrade(&self.shared))
+	}
+
+	/// Pauses playback.
+	pub fn pause(&self) {
+		self.shared.update(|c| c.paused = true);
+	}
+
+	/// Resumes playback.
+	pub fn resume(&self) {
+		self.shared.update(|c| c.paused = false);
+	}
+
+	/// Pauses playback if it's playing, resumes it otherwise.
+	pub fn toggle_pause(&self) {
+		self.shared.update(|c| c.paused = !c.paused);
+	}
+
+	/// Stops playback; [Player::play](crate::Player::play) returns with
+	/// [PlaybackEnd::Stopped](crate::PlaybackEnd::Stopped).
+	///
+	/// Does nothing if the player is not playing.
+	pub fn stop(&self) {
+		self.shared.update(|c| c.stop = c.running);
+	}
+
+	/// Jumps to `tick`, chasing the state of the skipped events the same way
+	/// [Player::play_from](crate::Player::play_from) does.
+	///
+	/// To seek to a point in time, convert it to a tick with
+	/// [TempoMap::tick_at](crate::TempoMap::tick_at); to seek to a bar, use
+	/// [BarMap::tick_at](crate::BarMap::tick_at).
+	pub fn seek(&self, tick: u32) {
+		self.shared.update(|c| c.seek = Some(tick));
+	}
+
+	/// Changes the speed of playback, see [Timer::set_speed](crate::Timer::set_speed).
+	///
+	/// The new speed takes effect starting from the next event. It is clamped
+	/// to the range `0.01..=100.0`; a speed that is not finite and positive
+	/// (such as 0, a negative value or NaN) is ignored.
+	pub fn set_speed(&self, speed: f32) {
+		if speed.is_finite() && speed > 0.0 {
+			let speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+			self.shared.update(|c| c.speed = Some(speed));
+		}
+	}
+
+	/// Mutes or unmutes `channel`.
+	///
+	/// Notes held on a channel when it is silenced are released. When it can
+	/// be heard again, its state (program, controllers and so on) is chased
+	/// the same way [Player::play_from](crate::Player::play_from) does, but
+	/// notes that started while it was silent are not played.
+	pub fn set_channel_muted(&self, channel: u4, muted: bool) {
+		self.update_mix(|m| m.set_channel_muted(channel, muted));
+	}
+
+	/// Solos or unsolos `channel`.
+	///
+	/// While any channel or track is soloed, only the soloed channels and
+	/// tracks can be heard; muting takes precedence over soloing.
+	pub fn set_channel_soloed(&self, channel: u4, soloed: bool) {
+		self.update_mix(|m| m.set_channel_soloed(channel, soloed));
+	}
+
+	/// Mutes or unmutes a track, given its index in
+	/// [Song::tracks](crate::Song::tracks).
+	///
+	/// Tracks are only known while playing a [Song](crate::Song) with
+	/// [Player::play_song](crate::Player::play_song); notes held on a channel
+	/// used by the track are released, as in [PlayerHandle::set_channel_muted].
+	pub fn set_track_muted(&self, track: usize, muted: bool) {
+		self.update_mix(|m| m.set_track_muted(track, muted));
+	}
+
+	/// Solos or unsolos a track, given its index in
+	/// [Song::tracks](crate::Song::tracks).
+	///
+	/// While a track is soloed, events that do not come from a track, such
+	/// as those of a [Sheet](crate::Sheet), are silenced too, unless their
+	/// channel is soloed.
+	pub fn set_track_soloed(&self, track: usize, soloed: bool) {
+		self.update_mix(|m| m.set_track_soloed(track, soloed));
+	}
+
+	/// Unmutes and unsolos every channel and track.
+	pub fn clear_mix(&self) {
+		self.update_mix(|m| *m = Mix::default());
+	}
+
+	fn update_mix(&self, f: impl FnOnce(&mut Mix)) {
+		self.shared.update(|c| {
+			f(&mut c.mix);
+			c.mix_changed = true;
+		});
+	}
+
+	/// Returns the tick of the last event played, or the tick of the last
+	/// seek.
+	pub fn position(&self) -> u32 {
+		self.shared.position.load(Ordering::SeqCst)
+	}
+
+	/// Returns the current state of the player.
+	pub fn state(&self) -> PlaybackState {
+		let c = self.shared.lock();
+		match (c.running, c.paused) {
+			(false, _) => PlaybackState::Stopped,
+			(true, false) => PlaybackState::Playing,
+			(true, true) => PlaybackState::Paused,
+		}
+	}
+}
+
+impl Clone for PlayerHandle {
+	fn clone(&self) -> Self {
+		Self::new(Arc::clone(&self.shared))
+	}
+}
+
+impl Drop for PlayerHandle {
+	fn drop(&mut self) {
+		if self.shared.handles.fetch_sub(1, Ordering::SeqCst) == 1 {
+			// Wake the player up in case it's paused.
+			let _c = self.shared.lock();
+			self.shared.cvar.notify_all();
+		}
+	}
+}
+
+// The commands of `PlayerHandle`, doing nothing once the player is gone.
+impl WeakHandle {
+	pub(crate) fn pause(&self) {
+		self.update(|c| c.paused = true);
+	}
+
+	pub(crate) fn resume(&self) {
+		self.update(|c| c.paused = false);
+	}
+
+	pub(crate) fn seek(&self, tick: u32) {
+		self.update(|c| c.seek = Some(tick));
+	}
+
+	// Whether the player is in the middle of playback, paused or not.
+	pub(crate) fn is_running(&self) -> bool {
+		self.0.upgrade().is_some_and(|s| s.lock().running)
+	}
+
+	fn update(&self, f: impl FnOnce(&mut Control)) {
+		if let Some(shared) = self.0.upgrade() {
+			shared.update(f);
+		}
+	}
+}
+
+impl Shared {
+	fn lock(&self) -> MutexGuard<'_, Control> {
+		self.control.lock().unwrap_or_else(|e| e.into_inner())
+	}
+
+	fn update(&self, f: impl FnOnce(&mut Control)) {
+		f(&mut self.lock());
+		self.cvar.notify_all();
+	}
+
+	/// Returns `true` if there are any live [PlayerHandle]s.
+	pub(crate) fn is_controlled(&self) -> bool {
+		self.handles.load(Ordering::SeqCst) > 0
+	}
+
+	pub(crate) fn position(&self) -> u32 {
+		self.position.load(Ordering::SeqCst)
+	}
+
+	pub(crate) fn set_position(&self, tick: u32) {
+		self.position.store(tick, Ordering::SeqCst);
+	}
+
+	/// Returns a pending command, if there is one.
+	fn interrupt(&self, c: &mut Control, remaining: Duration) -> Option<Interrupt> {
+		if c.stop || (c.paused && !self.is_controlled()) {
+			c.stop = false;
+			Some(Interrupt::Stop)
+		} else if let Some(tick) = c.seek.take() {
+			Some(Interrupt::Seek(tick))
+		} else if c.paused {
+			Some(Interrupt::Pause(remaining))
+		} else if c.mix_changed {
+			Some(Interrupt::Mix(remaining))
+		} else {
+			None
+		}
+	}
+
+	/// Waits for `t`, returning early if a command is received.
+	pub(crate) fn wait(&self, t: Duration) -> Option<Interrupt> {
+		let deadline = Instant::now() + t;
+		let mut c = self.lock();
+
+		loop {
+			let now = Instant::now();
+			let remaining = deadline.saturating_duration_since(now);
+			if let Some(i) = self.interrupt(&mut c, remaining) {
+				return Some(i);
+			}
+			if remaining <= SPIN {
+				break;
+			}
+			c = self
+				.cvar
+				.wait_timeout(c, remaining - SPIN)
+				.unwrap_or_else(|e| e.into_inner())
+				.0;
+		}
+
+		drop(c);
+		let remaining = deadline.saturating_duration_since(Instant::now());
+		if !remaining.is_zero() {
+			sleep(remaining);
+		}
+		None
+	}
+
+	/// Blocks while paused; returns the command that ended the pause, if any.
+	pub(crate) fn wait_paused(&self) -> Option<Interrupt> {
+		let mut c = self.lock();
+		loop {
+			match self.interrupt(&mut c, Duration::ZERO) {
+				Some(Interrupt::Pause(_)) => {
+					c = self.cvar.wait(c).unwrap_or_else(|e| e.into_inner());
+				}
+				other => return other,
+			}
+		}
+	}
+
+	pub(crate) fn set_running(&self, running: bool) {
+		let controlled = self.is_controlled();
+		self.update(|c| {
+			c.running = running;
+			c.stop = false;
+			// A pause nobody can undo anymore is dropped.
+			c.paused &= controlled;
+		});
+	}
+
+	pub(crate) fn take_speed(&self) -> Option<f32> {
+		self.lock().speed.take()
+	}
+
+	/// Returns the muting and soloing, if it changed since the last call.
+	pub(crate) fn take_mix(&self) -> Option<Mix> {
+		let mut c = self.lock();
+		if c.mix_changed {
+			c.mix_changed = false;
+			Some(c.mix.clone())
+		} else {
+			None
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::thread;
+
+	use super::*;
+	use crate::{
+		timers::Ticker, Connection, ConnectionError, Event, MidiEvent, Moment, PlaybackEnd, Player,
+		Recorder, Release, Timer,
+	};
+
+	struct Count(usize);
+
+	impl Connection for Count {
+		fn play(&mut self, _: MidiEvent) -> Result<(), ConnectionError> {
+			self.0 += 1;
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn stop_while_sleeping() {
+		let note = Event::Midi(MidiEvent {
+			channel: 0.into(),
+			message: midly::MidiMessage::NoteOn {
+				key: 60.into(),
+				vel: 64.into(),
+			},
+		});
+		// One beat is 10 seconds long.
+		let sheet = [
+			Moment::with_events(0, vec![Event::Tempo(10_000_000), note.clone()]),
+			Moment::with_events(1, vec![note]),
+		];
+
+		let mut player = Player::new(Ticker::new(1), Count(0));
+		let handle = player.handle();
+		assert_eq!(handle.state(), PlaybackState::Stopped);
+
+		let start = Instant::now();
+		let t = thread::spawn({
+			let handle = handle.clone();
+			move || {
+				thread::sleep(Duration::from_millis(50));
+				assert_eq!(handle.state(), PlaybackState::Playing);
+				handle.pause();
+				thread::sleep(Duration::from_millis(20));
+				assert_eq!(handle.state(), PlaybackState::Paused);
+				handle.stop();
+			}
+		});
+
+		let playback = player.play(&sheet).unwrap();
+		assert_eq!(playback.end, PlaybackEnd::Stopped);
+		assert_eq!(playback.tick, 0);
+		t.join().unwrap();
+		assert!(start.elapsed() < Duration::from_secs(5));
+		// The note and the NoteOff releasing it on pause.
+		assert_eq!(player.con.0, 2);
+		assert_eq!(handle.position(), 0);
+		assert_eq!(handle.state(), PlaybackState::Stopped);
+	}
+
+	#[test]
+	fn resume_restores_controllers() {
+		let bend = MidiEvent {
+			channel: 0.into(),
+			message: midly::MidiMessage::PitchBend {
+				bend: midly::PitchBend(0x3000.into()),
+			},
+		};
+		// A tick is 200ms long.
+		let sheet = [
+			Moment::with_events(0, vec![Event::Tempo(200_000), Event::Midi(bend)]),
+			Moment::new(1),
+		];
+		let mut player = Player::new(Ticker::new(1), Recorder::default());
+		player.set_release(Release {
+			reset_controllers: true,
+			..Release::default()
+		});
+		let handle = player.handle();
+		let t = thread::spawn(move || {
+			thread::sleep(Duration::from_millis(50));
+			handle.pause();
+			thread::sleep(Duration::from_millis(20));
+			handle.resume();
+		});
+		player.play(&sheet).unwrap();
+		t.join().unwrap();
+
+		let events = player
+			.con
+			.events
+			.iter()
+			.map(|(_, e)| *e)
+			.collect::<Vec<_>>();
+		assert_eq!(events.len(), 1 + 16 + 1);
+		assert_eq!(events[0], bend);
+		assert_eq!(events[17], bend);
+	}
+
+	// Waits a millisecond per tick, through the player.
+	struct Steps(u32);
+
+	impl Timer for Steps {
+		fn sleep_duration(&mut self, n_ticks: u32) -> Duration {
+			Duration::from_millis(n_ticks as u64)
+		}
+
+		fn change_tempo(&mut self, _: u32) {}
+
+		fn sleep(&mut self, _: u32) {
+			unreachable!("a handle exists");
+		}
+
+		fn sleep_with(&mut self, n_ticks: u32, wait: &mut dyn FnMut(Duration) -> bool) {
+			for _ in 0..n_ticks {
+				self.0 += 1;
+				if !wait(Duration::from_millis(1)) {
+					return;
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn timer_drives_wait() {
+		let sheet = [Moment::new(0), Moment::new(5)];
+		let mut player = Player::new(Steps(0), Count(0));
+		let _handle = player.handle();
+		player.play(&sheet).unwrap();
+		assert_eq!(player.timer.0, 5);
+	}
+
+	#[test]
+	fn valid_speed() {
+		let shared = Arc::new(Shared::default());
+		let handle = PlayerHandle::new(Arc::clone(&shared));
+		for speed in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+			handle.set_speed(speed);
+			assert_eq!(shared.take_speed(), None);
+		}
+		handle.set_speed(1000.0);
+		assert_eq!(shared.take_speed(), Some(100.0));
+		handle.set_speed(0.5);
+		assert_eq!(shared.take_speed(), Some(0.5));
+	}
+}
@@ -0,0 +1,218 @@
+use midly::MidiMessage;
+
+use crate::{BarMap, Event, MidiEvent, Sheet};
+
+// Controller marking the start of a loop in RPG Maker and other game MIDI.
+const LOOP_START_CC: u8 = 111;
+
+/// A region of a track that a [Player](crate::Player) plays repeatedly, see
+/// [Player::set_loop](crate::Player::set_loop).
+///
+/// When playback reaches [Loop::end], it jumps back to [Loop::start] until the
+/// region was played [Loop::count] times, then goes on to the end of the
+/// track.
+///
+/// # Examples
+/// ```
+/// use nodi::{BarMap, Loop, Sheet};
+///
+/// let sheet = Sheet::new();
+/// // Bars 5 to 8, four times.
+/// let map = BarMap::new(&sheet, 96);
+/// let lp = Loop {
+///     count: Some(4),
+///     ..Loop::bars(&map, 5, 8)
+/// };
+/// assert_eq!((lp.start, lp.end), (4 * 384, 8 * 384));
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Loop {
+	/// The first tick of the region.
+	pub start: u32,
+	/// The tick after the end of the region: events at this tick are played
+	/// only after the last repetition. A loop that ends at or before its
+	/// start is ignored.
+	pub end: u32,
+	/// How many times the region is played in total; `None` loops forever.
+	pub count: Option<u32>,
+}
+
+impl Loop {
+	/// Creates a [Loop] over the ticks in `start..end`, repeating forever.
+	pub fn new(start: u32, end: u32) -> Self {
+		Self {
+			start,
+			end,
+			count: None,
+		}
+	}
+
+	/// Creates a [Loop] from the start of bar `first` to the end of bar
+	/// `last`, repeating forever.
+	///
+	/// Bars are numbered as in [BarMap::bar_range].
+	pub fn bars(map: &BarMap, first: u32, last: u32) -> Self {
+		Self::new(map.bar_start(first), map.bar_range(last).end)
+	}
+
+	/// Creates a [Loop] between two [markers](Event::Marker), repeating
+	/// forever.
+	///
+	/// Marker texts are compared ignoring ASCII case. The loop starts at the
+	/// first marker named `start` and ends at the first marker named `end`
+	/// after it or, if there is none, after the last tick of the sheet, so
+	/// events on that tick are part of the loop. Returns `None`
+	/// if there is no `start` marker.
+	pub fn markers(sheet: &Sheet, start: &str, end: &str) -> Option<Self> {
+		let find = |name: &str, from: u32| {
+			sheet
+				.iter()
+				.filter(|m| m.tick() >= from)
+				.find(|m| {
+					m.iter().any(
+						|e| matches!(e, Event::Marker(s) if s.eq_ignore_ascii_case(name.as_bytes())),
+					)
+				})
+				.map(|m| m.tick())
+		};
+
+		let start = find(start, 0)?;
+		let end = find(end, start + 1).unwrap_or(sheet.len);
+		Some(Self::new(start, end))
+	}
+
+	/// Finds the loop a game MIDI file asks for, repeating forever.
+	///
+	/// Two conventions are recognised, in this order:
+	/// - `loopStart` and `loopEnd` markers, see [Loop::markers].
+	/// - A controller 111 message marking the start of the loop, which ends at
+	///   the end of the sheet, as with a missing `loopEnd` marker.
+	pub fn find(sheet: &Sheet) -> Option<Self> {
+		Self::markers(sheet, "loopStart", "loopEnd").or_else(|| {
+			let start = sheet
+				.iter()
+				.find(|m| {
+					m.iter().any(|e| {
+						matches!(
+							e,
+							Event::Midi(MidiEvent {
+								message: MidiMessage::Controller { controller, .. },
+								..
+							}) if *controller == LOOP_START_CC
+						)
+					})
+				})?
+				.tick();
+			Some(Self::new(start, sheet.len))
+		})
+	}
+
+	pub(crate) fn is_valid(&self) -> bool {
+		self.start < self.end && self.count != Some(0)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		player::Recorder,
+		timers::{Ticker, VirtualClock, VirtualTimer},
+		Moment, Player,
+	};
+
+	fn note(tick: u32, key: u8) -> Moment {
+		Moment::with_events(
+			tick,
+			vec![Event::Midi(MidiEvent {
+				channel: 0.into(),
+				message: MidiMessage::NoteOn {
+					key: key.into(),
+					vel: 100.into(),
+				},
+			})],
+		)
+	}
+
+	#[test]
+	fn play_loop() {
+		let mut sheet =
+			Sheet::from_moments([note(0, 60), note(48, 62), note(96, 64), note(144, 65)]);
+		sheet.insert(24, Event::Marker(b"LoopStart".to_vec()));
+		sheet.insert(120, Event::Marker(b"loopend".to_vec()));
+		sheet.insert(0, Event::Tempo(500_000));
+		let lp = Loop::find(&sheet).unwrap();
+		assert_eq!(lp, Loop::new(24, 120));
+
+		let clock = VirtualClock::new();
+		let timer = VirtualTimer::new(Ticker::new(96), clock.clone());
+		let mut player = Player::new(timer, Recorder::new(clock));
+		player.set_loop(Some(Loop {
+			count: Some(3),
+			..lp
+		}));
+		player.play(&sheet).unwrap();
+
+		let notes = player
+			.con
+			.events
+			.iter()
+			.filter(|(_, e)| matches!(e.message, MidiMessage::NoteOn { .. }))
+			.map(|(t, e)| (t.as_micros(), e.message))
+			.collect::<Vec<_>>();
+		let keys = notes.iter().map(|(_, m)| match m {
+			MidiMessage::NoteOn { key, .. } => key.as_int(),
+			_ => 0,
+		});
+		assert!(keys.eq([60, 62, 64, 62, 64, 62, 64, 65]));
+		// The region is a beat long.
+		assert_eq!(notes[3].0 - notes[1].0, 500_000);
+		assert_eq!(notes[5].0 - notes[3].0, 500_000);
+		// Held notes are released at every loop point, then at the end.
+		assert_eq!(player.con.events.len(), 8 + 3 + 2 + 3);
+
+		let bass = Sheet::from_moments([
+			note(0, 40),
+			Moment::with_events(
+				10,
+				vec![Event::Midi(MidiEvent {
+					channel: 0.into(),
+					message: MidiMessage::Controller {
+						controller: 111.into(),
+						value: 0.into(),
+					},
+				})],
+			),
+			Moment::new(100),
+		]);
+		assert_eq!(Loop::find(&bass), Some(Loop::new(10, 101)));
+	}
+
+	#[test]
+	fn loop_to_end() {
+		let mut sheet = Sheet::from_moments([note(0, 60), note(48, 62), note(96, 64)]);
+		sheet.insert(48, Event::Marker(b"loopStart".to_vec()));
+		let lp = Loop::find(&sheet).unwrap();
+		assert_eq!(lp, Loop::new(48, 97));
+
+		let clock = VirtualClock::new();
+		let timer = VirtualTimer::new(Ticker::new(96), clock.clone());
+		let mut player = Player::new(timer, Recorder::new(clock));
+		player.set_loop(Some(Loop {
+			count: Some(3),
+			..lp
+		}));
+		player.play(&sheet).unwrap();
+
+		// The note on the final tick is played on every repetition.
+		let keys = player
+			.con
+			.events
+			.iter()
+			.filter_map(|(_, e)| match e.message {
+				MidiMessage::NoteOn { key, .. } => Some(key.as_int()),
+				_ => None,
+			});
+		assert!(keys.eq([60, 62, 64, 62, 64, 62, 64]));
+	}
+}
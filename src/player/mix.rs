@@ -0,0 +1,288 @@
+use std::collections::BTreeSet;
+
+use midly::num::u4;
+
+// Which channels and tracks are muted or soloed, see `PlayerHandle`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Mix {
+	// One bit per channel.
+	muted: u16,
+	soloed: u16,
+	muted_tracks: BTreeSet<usize>,
+	soloed_tracks: BTreeSet<usize>,
+}
+
+impl Mix {
+	pub(crate) fn set_channel_muted(&mut self, channel: u4, muted: bool) {
+		set_bit(&mut self.muted, channel, muted);
+	}
+
+	pub(crate) fn set_channel_soloed(&mut self, channel: u4, soloed: bool) {
+		set_bit(&mut self.soloed, channel, soloed);
+	}
+
+	pub(crate) fn set_track_muted(&mut self, track: usize, muted: bool) {
+		set_member(&mut self.muted_tracks, track, muted);
+	}
+
+	pub(crate) fn set_track_soloed(&mut self, track: usize, soloed: bool) {
+		set_member(&mut self.soloed_tracks, track, soloed);
+	}
+
+	fn any_solo(&self) -> bool {
+		self.soloed != 0 || !self.soloed_tracks.is_empty()
+	}
+
+	// Whether an event on `channel` from `track` is heard; `None` is a track
+	// that is neither muted nor soloed.
+	pub(crate) fn is_audible(&self, channel: u4, track: Option<usize>) -> bool {
+		let bit = 1 << channel.as_int();
+		if self.muted & bit != 0 {
+			return false;
+		}
+		match track {
+			Some(t) if self.muted_tracks.contains(&t) => false,
+			_ if !self.any_solo() || self.soloed & bit != 0 => true,
+			Some(t) => self.soloed_tracks.contains(&t),
+			None => false,
+		}
+	}
+
+	// Whether anything on `channel` can be heard, whatever the track.
+	pub(crate) fn is_channel_audible(&self, channel: u4) -> bool {
+		self.is_audible(channel, None)
+			|| (self.muted & (1 << channel.as_int()) == 0
+				&& self
+					.soloed_tracks
+					.iter()
+					.any(|t| !self.muted_tracks.contains(t)))
+	}
+
+	// Returns whether some source on `channel` was silenced and whether some
+	// source became audible, going from `self` to `new`.
+	pub(crate) fn changes(&self, new: &Self, channel: u4) -> (bool, bool) {
+		// Tracks with no settings all behave like `None`.
+		let tracks = [self, new]
+			.into_iter()
+			.flat_map(|m| m.muted_tracks.iter().chain(&m.soloed_tracks))
+			.map(|&t| Some(t))
+			.chain([None]);
+
+		let (mut silenced, mut unmuted) = (false, false);
+		for t in tracks {
+			let (old, new) = (self.is_audible(channel, t), new.is_audible(channel, t));
+			silenced |= old && !new;
+			unmuted |= !old && new;
+		}
+		(silenced, unmuted)
+	}
+}
+
+fn set_bit(bits: &mut u16, channel: u4, on: bool) {
+	let bit = 1 << channel.as_int();
+	if on {
+		*bits |= bit;
+	} else {
+		*bits &= !bit;
+	}
+}
+
+fn set_member(set: &mut BTreeSet<usize>, track: usize, on: bool) {
+	if on {
+		set.insert(track);
+	} else {
+		set.remove(&track);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use midly::{Format, MidiMessage, Timing};
+
+	use super::*;
+	use crate::{
+		timers::Ticker, Connection, ConnectionError, Event, Loop, MidiEvent, Moment, Player,
+		PlayerHandle, Sheet, Song, SongTrack,
+	};
+
+	// Mutes channel 0 when key 60 is played, unmutes it on key 61; mutes
+	// track 1 on key 72.
+	#[derive(Default)]
+	struct Muter {
+		handle: Option<PlayerHandle>,
+		events: Vec<MidiEvent>,
+	}
+
+	impl Connection for Muter {
+		fn play(&mut self, event: MidiEvent) -> Result<(), ConnectionError> {
+			self.events.push(event);
+			if let (MidiMessage::NoteOn { key, .. }, Some(h)) = (event.message, &self.handle) {
+				if key == 60 || key == 61 {
+					h.set_channel_muted(0.into(), key == 60);
+				} else if key == 72 {
+					h.set_track_muted(1, true);
+				}
+			}
+			Ok(())
+		}
+	}
+
+	fn ev(channel: u8, message: MidiMessage) -> MidiEvent {
+		MidiEvent {
+			channel: channel.into(),
+			message,
+		}
+	}
+
+	fn on(channel: u8, key: u8) -> MidiEvent {
+		ev(
+			channel,
+			MidiMessage::NoteOn {
+				key: key.into(),
+				vel: 100.into(),
+			},
+		)
+	}
+
+	fn off(channel: u8, key: u8) -> MidiEvent {
+		ev(
+			channel,
+			MidiMessage::NoteOff {
+				key: key.into(),
+				vel: 64.into(),
+			},
+		)
+	}
+
+	#[test]
+	fn mute_and_solo() {
+		let (ch0, ch1) = (u4::new(0), u4::new(1));
+		let mut mix = Mix::default();
+		assert!(mix.is_audible(ch0, None) && mix.is_audible(ch1, Some(3)));
+
+		let old = mix.clone();
+		mix.set_channel_muted(ch0, true);
+		mix.set_track_soloed(2, true);
+		assert!(!mix.is_audible(ch0, Some(2)));
+		assert!(mix.is_audible(ch1, Some(2)));
+		assert!(!mix.is_audible(ch1, Some(3)) && !mix.is_audible(ch1, None));
+		assert!(mix.is_channel_audible(ch1) && !mix.is_channel_audible(ch0));
+		assert_eq!(old.changes(&mix, ch0), (true, false));
+		assert_eq!(old.changes(&mix, ch1), (true, false));
+
+		let old = mix.clone();
+		mix.set_channel_soloed(ch1, true);
+		mix.set_track_muted(4, true);
+		assert!(mix.is_audible(ch1, Some(3)));
+		assert!(!mix.is_audible(ch1, Some(4)));
+		assert_eq!(old.changes(&mix, ch1), (false, true));
+		assert_eq!(mix.changes(&Mix::default(), ch0), (false, true));
+	}
+
+	#[test]
+	fn live_mute() {
+		let program = ev(0, MidiMessage::ProgramChange { program: 5.into() });
+		let sheet = Sheet::from_moments([
+			Moment::with_events(0, vec![Event::Midi(program), Event::Midi(on(0, 60))]),
+			Moment::with_events(1, vec![Event::Midi(on(0, 62)), Event::Midi(on(1, 61))]),
+			Moment::with_events(2, vec![Event::Midi(on(0, 64))]),
+		]);
+
+		let mut player = Player::new(Ticker::new(96), Muter::default());
+		player.con.handle = Some(player.handle());
+		player.play(&sheet).unwrap();
+		assert_eq!(
+			player.con.events,
+			[
+				program,
+				on(0, 60),
+				off(0, 60),
+				on(1, 61),
+				program,
+				on(0, 64),
+				off(0, 64),
+				off(1, 61),
+			]
+		);
+
+		// Two tracks sharing channel 2, the second one muted.
+		let mut song = Song::new(Format::Parallel, Timing::Metrical(96.into()));
+		for key in [70, 71] {
+			let track =
+				Sheet::from_moments([Moment::with_events(0, vec![Event::Midi(on(2, key))])]);
+			song.tracks.push(SongTrack::new(track));
+		}
+		let mut player = Player::new(Ticker::new(96), Muter::default());
+		player.handle().set_track_muted(1, true);
+		player.play_song(&song).unwrap();
+		assert_eq!(player.con.events, [on(2, 70), off(2, 70)]);
+	}
+
+	#[test]
+	fn track_mute() {
+		let program = |p: u8| ev(2, MidiMessage::ProgramChange { program: p.into() });
+		let song = |tracks: Vec<Vec<Moment>>| {
+			let mut song = Song::new(Format::Parallel, Timing::Metrical(96.into()));
+			for track in tracks {
+				song.tracks.push(SongTrack::new(Sheet::from_moments(track)));
+			}
+			song
+		};
+
+		// Muting a track releases its notes only, not those of other tracks on
+		// the same channel.
+		let two = song(vec![
+			vec![
+				Moment::with_events(0, vec![Event::Midi(on(2, 70))]),
+				Moment::with_events(1, vec![Event::Midi(on(3, 72))]),
+				Moment::with_events(2, vec![Event::Midi(on(3, 73))]),
+			],
+			vec![Moment::with_events(0, vec![Event::Midi(on(2, 71))])],
+		]);
+		let mut player = Player::new(Ticker::new(96), Muter::default());
+		player.con.handle = Some(player.handle());
+		player.play_song(&two).unwrap();
+		assert_eq!(
+			player.con.events,
+			[
+				on(2, 70),
+				on(2, 71),
+				on(3, 72),
+				off(2, 71),
+				on(3, 73),
+				off(2, 70),
+				off(3, 72),
+				off(3, 73),
+			]
+		);
+
+		// The state chased at the start of a loop leaves out muted tracks.
+		let two = song(vec![
+			vec![
+				Moment::with_events(0, vec![Event::Midi(program(5))]),
+				Moment::with_events(1, vec![Event::Midi(on(2, 70))]),
+			],
+			vec![Moment::with_events(0, vec![Event::Midi(program(9))])],
+		]);
+		let mut player = Player::new(Ticker::new(96), Muter::default());
+		player.handle().set_track_muted(1, true);
+		player.set_loop(Some(Loop {
+			start: 1,
+			end: 2,
+			count: Some(2),
+		}));
+		player.play_song(&two).unwrap();
+		let program = program(5);
+		assert_eq!(
+			player.con.events,
+			[
+				program,
+				on(2, 70),
+				off(2, 70),
+				program,
+				on(2, 70),
+				off(2, 70)
+			]
+		);
+	}
+}
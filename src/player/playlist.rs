@@ -0,0 +1,412 @@
+use std::{panic, thread, time::Duration};
+
+use midly::live::SystemRealtime;
+
+use super::{Connection, PlayError, Playback, PlaybackEnd, Player, Release};
+use crate::{sheet::Rng, Rounding, Sheet, Timer};
+
+/// How a [Playlist] repeats, see [Playlist::repeat].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum RepeatMode {
+	/// Play every item once.
+	#[default]
+	Off,
+	/// Play the first item over and over.
+	One,
+	/// Play every item, then start over; a shuffled playlist is shuffled
+	/// again every time.
+	All,
+}
+
+/// Something that happened while playing a [Playlist].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlaylistEvent<E> {
+	/// The item at this index of [Playlist::items] started playing.
+	Started(usize),
+	/// The item at this index of [Playlist::items] could not be loaded and
+	/// was skipped.
+	Skipped(usize, E),
+}
+
+/// A queue of tracks played one after another by a [Player], without gaps.
+///
+/// Items can be anything a [Sheet] can be loaded from, such as file paths;
+/// while an item plays, the next one is loaded on another thread. The
+/// [Timer] is not reset between items, so no gap or drift is introduced; the
+/// tempo is set back to 120 BPM at the start of every item, as MIDI files
+/// expect.
+///
+/// Every item is played once: the [loop](Player::set_loop) of the player is
+/// ignored. If the player is a [clock master](Player::set_clock), followers
+/// are sent Start before the first item and Stop after the last one, or when
+/// playback stops; the clock keeps running between items.
+///
+/// # Examples
+/// ```no_run
+/// use nodi::{timers::Ticker, Connection, Player, Playlist, PlaylistEvent, Sheet};
+///
+/// fn jukebox<C: Connection>(con: C, files: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
+///     let mut player = Player::new(Ticker::new(480), con);
+///     let playlist = Playlist::new(files, 480);
+///     let load = |path: &String| -> Result<Sheet, std::io::Error> {
+///         let data = std::fs::read(path)?;
+///         let song = nodi::Song::parse(&data)
+///             .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+///         Ok(song.sheet())
+///     };
+///
+///     playlist.play(&mut player, load, |event| match event {
+///         PlaylistEvent::Started(i) => println!("playing {}", playlist.items[i]),
+///         PlaylistEvent::Skipped(i, e) => eprintln!("skipping {}: {e}", playlist.items[i]),
+///     })?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Playlist<P> {
+	/// The items to play.
+	pub items: Vec<P>,
+	/// How the playlist repeats.
+	pub repeat: RepeatMode,
+	/// If set, items are played in a random order, shuffled with this seed.
+	pub shuffle: Option<u64>,
+	/// The ticks per beat of the [Timer] used. Sheets that know their own
+	/// [ticks per beat](Sheet::ticks_per_beat) are resampled to it.
+	pub ticks_per_beat: Option<u16>,
+	/// What is sent on every channel between two items, after the notes still
+	/// held are released.
+	pub reset: Release,
+}
+
+impl<P> Playlist<P> {
+	/// Creates a [Playlist] playing `items` once, in order, for a timer with
+	/// the given ticks per beat; controllers are reset between items.
+	pub fn new(items: Vec<P>, ticks_per_beat: u16) -> Self {
+		Self {
+			items,
+			repeat: RepeatMode::Off,
+			shuffle: None,
+			ticks_per_beat: Some(ticks_per_beat),
+			reset: Release {
+				reset_controllers: true,
+				..Release::default()
+			},
+		}
+	}
+
+	/// Plays the playlist with `player`, loading items with `load` and
+	/// reporting what happens to `on_event`.
+	///
+	/// Items that fail to load are skipped; if every item fails in a row, or
+	/// the item repeated with [RepeatMode::One] fails, playback ends. Stopping `player` with a
+	/// [PlayerHandle](crate::PlayerHandle) stops the playlist.
+	///
+	/// Returns a [Playback] with the tick of the last item played and the
+	/// time spent playing every item.
+	///
+	/// # Errors
+	/// Stops playing and returns an error if the [Connection] fails.
+	pub fn play<T, C, F, E>(
+		&self,
+		player: &mut Player<T, C>,
+		load: F,
+		mut on_event: impl FnMut(PlaylistEvent<E>),
+	) -> Result<Playback, PlayError>
+	where
+		T: Timer,
+		C: Connection,
+		F: Fn(&P) -> Result<Sheet, E> + Sync,
+		P: Sync,
+		E: Send,
+	{
+		let load = |i: usize| {
+			load(&self.items[i]).map(|mut sheet| {
+				if let (Some(from), Some(to)) = (sheet.ticks_per_beat(), self.ticks_per_beat) {
+					sheet.resample(from, to, Rounding::Nearest);
+				}
+				sheet
+			})
+		};
+		let load = &load;
+		let mut order = Order::new(self);
+		let mut playback = Playback {
+			end: PlaybackEnd::Finished,
+			tick: 0,
+			elapsed: Duration::ZERO,
+		};
+		let mut failed = 0;
+		let mut played = false;
+		// Whether clock followers were left running by the last item.
+		let mut running = false;
+
+		let res = thread::scope(|s| {
+			let spawn = |i: usize| (i, s.spawn(move || load(i)));
+			let mut next = order.next().map(spawn);
+
+			while let Some((i, task)) = next.take() {
+				let loaded = task.join().unwrap_or_else(|e| panic::resume_unwind(e));
+				// Load the next item while this one plays.
+				next = order.next().map(spawn);
+
+				let sheet = match loaded {
+					Ok(sheet) => sheet,
+					Err(e) => {
+						on_event(PlaylistEvent::Skipped(i, e));
+						failed += 1;
+						if failed >= self.items.len() || self.repeat == RepeatMode::One {
+							break;
+						}
+						continue;
+					}
+				};
+				failed = 0;
+
+				if played {
+					player.send_release(self.reset).map_err(|error| PlayError {
+						error,
+						tick: playback.tick,
+						elapsed: playback.elapsed,
+					})?;
+				}
+				on_event(PlaylistEvent::Started(i));
+				running = false;
+				let p = player.play_next(&sheet, !played).map_err(|mut e| {
+					e.elapsed += playback.elapsed;
+					e
+				})?;
+				played = true;
+				running = p.end == PlaybackEnd::Finished;
+
+				playback = Playback {
+					end: p.end,
+					tick: p.tick,
+					elapsed: playback.elapsed + p.elapsed,
+				};
+				if p.end == PlaybackEnd::Stopped {
+					break;
+				}
+			}
+
+			Ok(playback)
+		});
+
+		if running {
+			let stopped = player.send_clock(SystemRealtime::Stop);
+			if let (Ok(p), Err(error)) = (&res, stopped) {
+				return Err(PlayError {
+					error,
+					tick: p.tick,
+					elapsed: p.elapsed,
+				});
+			}
+		}
+		res
+	}
+}
+
+// The order items are played in.
+struct Order {
+	len: usize,
+	repeat: RepeatMode,
+	rng: Option<Rng>,
+	// The rest of the current round, last item first.
+	queue: Vec<usize>,
+	started: bool,
+	first: Option<usize>,
+}
+
+impl Order {
+	fn new<P>(playlist: &Playlist<P>) -> Self {
+		Self {
+			len: playlist.items.len(),
+			repeat: playlist.repeat,
+			rng: playlist.shuffle.map(Rng),
+			queue: Vec::new(),
+			started: false,
+			first: None,
+		}
+	}
+}
+
+impl Iterator for Order {
+	type Item = usize;
+
+	fn next(&mut self) -> Option<usize> {
+		if self.first.is_some() {
+			return self.first;
+		}
+		if self.queue.is_empty() {
+			if self.started && self.repeat != RepeatMode::All {
+				return None;
+			}
+			self.started = true;
+			self.queue = (0..self.len).rev().collect();
+			if let Some(rng) = &mut self.rng {
+				// Fisher-Yates.
+				for k in (1..self.len).rev() {
+					let j = (rng.next() % (k as u64 + 1)) as usize;
+					self.queue.swap(k, j);
+				}
+			}
+		}
+
+		let i = self.queue.pop()?;
+		if self.repeat == RepeatMode::One {
+			self.first = Some(i);
+		}
+		Some(i)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use midly::{
+		live::{SystemCommon, SystemRealtime},
+		MidiMessage,
+	};
+
+	use super::*;
+	use crate::{
+		timers::{Ticker, VirtualClock, VirtualTimer},
+		ConnectionError, Event, Loop, MidiEvent, Moment, Recorder,
+	};
+
+	fn song(ticks_per_beat: u16, key: u8) -> Sheet {
+		let note = |tick| {
+			Moment::with_events(
+				tick,
+				vec![Event::Midi(MidiEvent {
+					channel: 0.into(),
+					message: MidiMessage::NoteOn {
+						key: key.into(),
+						vel: 100.into(),
+					},
+				})],
+			)
+		};
+		// A beat of notes, then a beat of silence.
+		let tpb = ticks_per_beat as u32;
+		let mut sheet = Sheet::from_moments([note(0), note(tpb / 2), Moment::new(2 * tpb)]);
+		sheet.set_ticks_per_beat(Some(ticks_per_beat));
+		sheet
+	}
+
+	#[test]
+	fn gapless() {
+		let mut playlist = Playlist::new(
+			vec![Ok(song(96, 60)), Err("bad file"), Ok(song(480, 62))],
+			96,
+		);
+		let clock = VirtualClock::new();
+		let timer = VirtualTimer::new(Ticker::new(96), clock.clone());
+		let mut player = Player::new(timer, Recorder::new(clock));
+
+		let mut events = Vec::new();
+		let playback = playlist
+			.play(&mut player, |item| item.clone(), |e| events.push(e))
+			.unwrap();
+		assert_eq!(
+			events,
+			[
+				PlaylistEvent::Started(0),
+				PlaylistEvent::Skipped(1, "bad file"),
+				PlaylistEvent::Started(2),
+			]
+		);
+		assert_eq!(playback.end, PlaybackEnd::Finished);
+		assert_eq!(playback.tick, 2 * 96);
+
+		let notes = player
+			.con
+			.events
+			.iter()
+			.filter(|(_, e)| matches!(e.message, MidiMessage::NoteOn { .. }))
+			.map(|(t, _)| t.as_millis())
+			.collect::<Vec<_>>();
+		assert_eq!(notes, [0, 250, 1000, 1250]);
+		// Every item is released, controllers are reset on every channel
+		// between items.
+		assert_eq!(player.con.events.len(), 4 + 2 + 16);
+
+		playlist.shuffle = Some(3);
+		playlist.repeat = RepeatMode::All;
+		let mut order = Order::new(&playlist).take(6).collect::<Vec<_>>();
+		order[..3].sort_unstable();
+		order[3..].sort_unstable();
+		assert_eq!(order, [0, 1, 2, 0, 1, 2]);
+		playlist.repeat = RepeatMode::One;
+		let order = Order::new(&playlist).take(3).collect::<Vec<_>>();
+		assert!(order.iter().all(|&i| i == order[0]));
+
+		// The repeated item fails once, not over and over.
+		let playlist = Playlist {
+			repeat: RepeatMode::One,
+			..Playlist::new(vec![Err("bad file"), Ok(song(96, 60))], 96)
+		};
+		let mut events = Vec::new();
+		playlist
+			.play(&mut player, |item| item.clone(), |e| events.push(e))
+			.unwrap();
+		assert_eq!(events, [PlaylistEvent::Skipped(0, "bad file")]);
+	}
+
+	#[derive(Debug, PartialEq)]
+	enum Sent {
+		On(u8),
+		Rt(SystemRealtime),
+		Spp(u16),
+	}
+
+	#[derive(Debug, Default)]
+	struct Log(Vec<Sent>);
+
+	impl Connection for Log {
+		fn play(&mut self, msg: MidiEvent) -> Result<(), ConnectionError> {
+			if let MidiMessage::NoteOn { key, .. } = msg.message {
+				self.0.push(Sent::On(key.as_int()));
+			}
+			Ok(())
+		}
+
+		fn send_sys_rt(&mut self, msg: SystemRealtime) -> Result<(), ConnectionError> {
+			if msg != SystemRealtime::TimingClock {
+				self.0.push(Sent::Rt(msg));
+			}
+			Ok(())
+		}
+
+		fn send_sys_common(&mut self, msg: SystemCommon<'_>) -> Result<(), ConnectionError> {
+			if let SystemCommon::SongPosition(pos) = msg {
+				self.0.push(Sent::Spp(pos.as_int()));
+			}
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn once_with_clock() {
+		let playlist = Playlist::new(vec![song(96, 60), song(96, 62)], 96);
+		let timer = VirtualTimer::new(Ticker::new(96), VirtualClock::new());
+		let mut player = Player::new(timer, Log::default());
+		player.set_loop(Some(Loop {
+			count: Some(2),
+			..Loop::new(0, 48)
+		}));
+		player.set_clock(Some(96));
+
+		playlist
+			.play(&mut player, |s| Ok::<_, ()>(s.clone()), |_| ())
+			.unwrap();
+		assert_eq!(
+			player.con.0,
+			[
+				Sent::Rt(SystemRealtime::Start),
+				Sent::On(60),
+				Sent::On(60),
+				Sent::On(62),
+				Sent::On(62),
+				Sent::Rt(SystemRealtime::Stop),
+			]
+		);
+	}
+}
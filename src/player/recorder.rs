@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use crate::{timers::VirtualClock, Connection, ConnectionError, MidiEvent};
+
+/// A [Connection] that records what it is sent, along with the time of a
+/// [VirtualClock].
+///
+/// Together with a [VirtualTimer](crate::timers::VirtualTimer) advancing the
+/// same clock, a [Player](crate::Player) plays a whole track instantly and the
+/// resulting timeline can be checked afterwards.
+#[derive(Debug, Clone, Default)]
+pub struct Recorder {
+	clock: VirtualClock,
+	/// Every [MidiEvent] played, with the time it was played at.
+	pub events: Vec<(Duration, MidiEvent)>,
+	/// Every SysEx message sent, with the time it was sent at.
+	pub sysex: Vec<(Duration, Vec<u8>)>,
+}
+
+impl Recorder {
+	/// Creates a [Recorder] that timestamps events using `clock`.
+	pub fn new(clock: VirtualClock) -> Self {
+		Self {
+			clock,
+			events: Vec::new(),
+			sysex: Vec::new(),
+		}
+	}
+
+	/// Returns the clock used for timestamps.
+	pub fn clock(&self) -> &VirtualClock {
+		&self.clock
+	}
+
+	/// Forgets everything recorded so far.
+	pub fn clear(&mut self) {
+		self.events.clear();
+		self.sysex.clear();
+	}
+}
+
+impl Connection for Recorder {
+	fn play(&mut self, event: MidiEvent) -> Result<(), ConnectionError> {
+		self.events.push((self.clock.now(), event));
+		Ok(())
+	}
+
+	fn send_sysex(&mut self, data: &[u8]) -> Result<(), ConnectionError> {
+		self.sysex.push((self.clock.now(), data.to_vec()));
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Instant;
+
+	use midly::MidiMessage;
+
+	use super::*;
+	use crate::{
+		timers::{Ticker, VirtualTimer},
+		Event, Moment, Player, Sheet,
+	};
+
+	#[test]
+	fn virtual_timeline() {
+		let note = |tick: u32, key: u8| {
+			Moment::with_events(
+				tick,
+				vec![Event::Midi(MidiEvent {
+					channel: 0.into(),
+					message: MidiMessage::NoteOn {
+						key: key.into(),
+						vel: 100.into(),
+					},
+				})],
+			)
+		};
+		// 120 BPM for 2 beats, then 30 BPM.
+		let mut sheet = Sheet::from_moments([note(0, 60), note(96, 62), note(192, 64)]);
+		sheet.insert(0, Event::Tempo(500_000));
+		sheet.insert(192, Event::Tempo(2_000_000));
+		sheet.push_at(note(288, 65));
+		// Ten minutes of silence.
+		sheet.push_at(note(288 + 96 * 300, 67));
+
+		let clock = VirtualClock::new();
+		let timer = VirtualTimer::new(Ticker::new(96), clock.clone());
+		let mut player = Player::new(timer, Recorder::new(clock.clone()));
+
+		let start = Instant::now();
+		player.play(&sheet).unwrap();
+		assert!(start.elapsed() < Duration::from_secs(1));
+
+		let times = player
+			.con
+			.events
+			.iter()
+			.filter(|(_, e)| matches!(e.message, MidiMessage::NoteOn { .. }))
+			.map(|(t, _)| t.as_millis())
+			.collect::<Vec<_>>();
+		assert_eq!(times, [0, 500, 1000, 3000, 3000 + 600_000]);
+	}
+}
@@ -0,0 +1,162 @@
+use std::{borrow::Cow, iter::FusedIterator, slice, time::Duration, vec};
+
+use crate::{ActiveNotes, Event, MidiEvent, Moment, Timer};
+
+/// An iterator over the events of a track and the time they play at, without
+/// any sleeping.
+///
+/// Times are measured from tick 0 and computed exactly like
+/// [Player::play](crate::Player::play) would: tempo changes are given to the
+/// timer as they are yielded, and the length of every gap is
+/// [Timer::nominal_duration]. This means a [Ticker](crate::timers::Ticker)'s
+/// `speed` is taken into account.
+///
+/// Like the player, the notes and sustain pedals still held after the last
+/// event are released: NoteOffs and pedal releases are yielded at the time of
+/// the last event, see [ActiveNotes::release]. These are the only owned
+/// events; every other event is borrowed from the track.
+///
+/// Created with [Sheet::render](crate::Sheet::render) or [Render::new].
+///
+/// # Examples
+/// ```
+/// use std::time::Duration;
+/// use nodi::{timers::Ticker, Event, Moment, Sheet};
+///
+/// let sheet = Sheet::from_moments([
+///     Moment::with_events(0, vec![Event::Tempo(1_000_000)]),
+///     Moment::with_events(48, vec![Event::Marker(b"half".to_vec())]),
+/// ]);
+/// let timeline = sheet.render(Ticker::new(96)).collect::<Vec<_>>();
+/// assert_eq!(timeline[1].0, Duration::from_millis(500));
+/// assert_eq!(*timeline[1].1, Event::Marker(b"half".to_vec()));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Render<'a, T: Timer> {
+	timer: T,
+	moments: slice::Iter<'a, Moment>,
+	events: slice::Iter<'a, Event>,
+	last_tick: u32,
+	time: Duration,
+	active: ActiveNotes,
+	// The releases yielded after the last event, once it is reached.
+	release: Option<vec::IntoIter<MidiEvent>>,
+}
+
+impl<'a, T: Timer> Render<'a, T> {
+	/// Creates a [Render] over `moments`, timed with `timer`.
+	pub fn new(moments: &'a [Moment], mut timer: T) -> Self {
+		timer.reset();
+		Self {
+			timer,
+			last_tick: 0,
+			moments: moments.iter(),
+			events: [].iter(),
+			time: Duration::ZERO,
+			active: ActiveNotes::new(),
+			release: None,
+		}
+	}
+
+	/// Returns the time of the last event yielded.
+	pub fn time(&self) -> Duration {
+		self.time
+	}
+
+	/// Returns the timer, with the tempo of the last event yielded.
+	pub fn into_timer(self) -> T {
+		self.timer
+	}
+}
+
+impl<'a, T: Timer> Iterator for Render<'a, T> {
+	type Item = (Duration, Cow<'a, Event>);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			if let Some(release) = &mut self.release {
+				return release
+					.next()
+					.map(|e| (self.time, Cow::Owned(Event::Midi(e))));
+			}
+
+			if let Some(event) = self.events.next() {
+				match event {
+					Event::Tempo(tempo) => self.timer.change_tempo(*tempo),
+					Event::Midi(msg) => self.active.update(msg),
+					_ => (),
+				}
+				return Some((self.time, Cow::Borrowed(event)));
+			}
+
+			let Some(moment) = self.moments.next() else {
+				self.release = Some(self.active.release().into_iter());
+				continue;
+			};
+			self.time += self.timer.nominal_duration(moment.tick() - self.last_tick);
+			self.last_tick = moment.tick();
+			self.events = moment.events.iter();
+		}
+	}
+}
+
+impl<T: Timer> FusedIterator for Render<'_, T> {}
+
+#[cfg(test)]
+mod tests {
+	use midly::MidiMessage;
+
+	use super::*;
+	use crate::{
+		timers::{Ticker, VirtualClock, VirtualTimer},
+		MidiEvent, Player, Recorder, Sheet,
+	};
+
+	#[test]
+	fn render_matches_player() {
+		let note = |key: u8, on: bool| {
+			let (key, vel) = (key.into(), 100.into());
+			Event::Midi(MidiEvent {
+				channel: 1.into(),
+				message: if on {
+					MidiMessage::NoteOn { key, vel }
+				} else {
+					MidiMessage::NoteOff { key, vel }
+				},
+			})
+		};
+		let mut sheet = Sheet::new();
+		sheet.insert(0, Event::Tempo(600_000));
+		sheet.insert(0, note(60, true));
+		sheet.insert(100, note(60, false));
+		sheet.insert(100, note(62, true));
+		sheet.insert(350, note(64, true));
+		sheet.insert(351, Event::Tempo(250_000));
+		sheet.insert(351, note(62, false));
+		// Still held at the end.
+		sheet.insert(1000, note(65, true));
+		let mut ticker = Ticker::new(120);
+		ticker.speed = 1.5;
+
+		let rendered = sheet
+			.render(ticker)
+			.filter_map(|(t, e)| match *e {
+				Event::Midi(m) => Some((t, m)),
+				_ => None,
+			})
+			.collect::<Vec<_>>();
+
+		let clock = VirtualClock::new();
+		let mut player = Player::new(
+			VirtualTimer::new(ticker, clock.clone()),
+			Recorder::new(clock),
+		);
+		player.play(&sheet).unwrap();
+
+		assert_eq!(rendered, player.con.events);
+		// Two notes are released at the end.
+		assert_eq!(rendered.len(), 6 + 2);
+		assert_eq!(rendered[5].0, Duration::from_micros(2_071_387));
+		assert_eq!(rendered[7].0, rendered[5].0);
+	}
+}
@@ -1,15 +1,35 @@
 use midly::TrackEvent;
 
-use crate::event::Moment;
+use crate::{
+	event::{merge_events, Event, EventOrder, Moment},
+	Render, Timer,
+};
 
 mod bar;
+mod humanize;
 mod impls;
+mod notes;
+mod quantize;
+mod resample;
+mod write;
 
-pub use bar::Bars;
+pub use bar::{Bar, Bars, BarsRef};
+pub use humanize::Humanize;
+pub(crate) use humanize::Rng;
+pub use notes::Note;
+pub use quantize::Quantize;
+pub use resample::Rounding;
+pub use write::TicksPerBeatError;
 
 #[doc = include_str!("doc_sheet.md")]
 #[derive(Default, Clone, Debug, Eq, PartialEq, Hash)]
-pub struct Sheet(pub(crate) Vec<Moment>);
+pub struct Sheet {
+	// Sorted by tick, no two moments share a tick.
+	pub(crate) moments: Vec<Moment>,
+	// Length in ticks; always greater than the tick of the last moment.
+	pub(crate) len: u32,
+	pub(crate) ticks_per_beat: Option<u16>,
+}
 
 impl Sheet {
 	/// Creates a [Sheet] from a slice of [TrackEvent]s.
@@ -26,7 +46,17 @@ impl Sheet {
 	/// # Notes
 	/// Use this when a MIDI file header specifies the format to be of 1,
 	/// meaning parallel.
+	///
+	/// Events from different tracks at the same tick are sorted as in
+	/// [Moment::sort_events]; use [Sheet::parallel_with_order] to keep them in
+	/// track order instead.
 	pub fn parallel(tracks: &[Vec<TrackEvent<'_>>]) -> Self {
+		Self::parallel_with_order(tracks, EventOrder::Canonical)
+	}
+
+	/// Same as [Sheet::parallel] but events at the same tick are ordered as
+	/// specified by `order`.
+	pub fn parallel_with_order(tracks: &[Vec<TrackEvent<'_>>], order: EventOrder) -> Self {
 		if tracks.is_empty() {
 			return Self::default();
 		}
@@ -35,7 +65,7 @@ impl Sheet {
 
 		for track in &tracks[1..] {
 			let sh = Self::from(track.as_slice());
-			first.merge_with(sh);
+			first.merge_with_order(sh, order);
 		}
 		first
 	}
@@ -55,74 +85,212 @@ impl Sheet {
 
 		for track in &tracks[1..] {
 			let sh = Self::from(track.as_slice());
-			first.extend(sh);
+			first.append(sh);
 		}
 		first
 	}
 
 	/// Creates a new, blank [Sheet].
 	pub fn new() -> Self {
-		Self(Vec::new())
+		Self::default()
 	}
 
-	/// Creates a new, blank [Sheet] with the given initial capacity.
+	/// Creates a [Sheet] holding `moments`, each at its own
+	/// [tick](Moment::tick), as with [Sheet::push_at].
+	///
+	/// This is the inverse of [Sheet::into_inner]. Collecting moments into a
+	/// [Sheet] appends them one tick after another instead, see
+	/// [Sheet::push].
+	pub fn from_moments(moments: impl IntoIterator<Item = Moment>) -> Self {
+		let mut sheet = Self::new();
+		for m in moments {
+			sheet.push_at(m);
+		}
+		sheet
+	}
+
+	/// Creates a new, blank [Sheet] with room for `cap` non-empty [Moment]s.
 	pub fn with_capacity(cap: usize) -> Self {
-		Self(Vec::with_capacity(cap))
+		Self {
+			moments: Vec::with_capacity(cap),
+			len: 0,
+			ticks_per_beat: None,
+		}
 	}
 
 	/// Destroys `self` yielding the underlying [Vec].
+	///
+	/// Only the stored [Moment]s are returned, each one carrying its
+	/// [tick](Moment::tick).
 	pub fn into_inner(self) -> Vec<Moment> {
-		self.0
+		self.moments
 	}
 
-	/// Returns how many MIDI ticks (or [Moment]s) this [Sheet] has.
+	/// Returns how many MIDI ticks this [Sheet] spans.
 	///
 	/// Note that multiplying this value with the length of a tick may not
 	/// always give you the correct total duration. The reason for this is a
 	/// MIDI file can change tempo mid-track, however it is still trivial to
 	/// calculate the duration since every tempo-change event will be contained
 	/// in `self`.
+	///
+	/// To get the number of stored [Moment]s, use `sheet.as_moments().len()`.
 	pub fn len(&self) -> usize {
-		self.0.len()
+		self.len as usize
 	}
 
 	/// Returns `Self::len() == 0`.
 	pub fn is_empty(&self) -> bool {
-		self.0.is_empty()
+		self.len == 0
+	}
+
+	/// Returns the ticks per beat (PPQ) of `self`, if known.
+	///
+	/// A [Sheet] does not know its resolution unless it is set with
+	/// [Sheet::set_ticks_per_beat] or [Sheet::resample].
+	pub fn ticks_per_beat(&self) -> Option<u16> {
+		self.ticks_per_beat
+	}
+
+	/// Sets the ticks per beat (PPQ) of `self`, without changing any tick.
+	///
+	/// Use this to record the resolution found in the header of a MIDI file,
+	/// so that [Sheet::merge_with] and [Sheet::append] can convert sheets of
+	/// different resolutions.
+	pub fn set_ticks_per_beat(&mut self, ticks_per_beat: Option<u16>) {
+		self.ticks_per_beat = ticks_per_beat;
 	}
 
 	/// Merges `self` with another [Sheet], destroying the other.
 	///
 	/// # Notes
 	/// This method will combine every moment in both [Sheet]s into one. If you
-	/// want to join them end to end instead, use [Sheet::extend].
+	/// want to join them end to end instead, use [Sheet::append].
+	///
+	/// If both sheets know their [ticks per beat](Sheet::ticks_per_beat) and
+	/// they differ, `other` is first resampled to the resolution of `self`
+	/// with [Rounding::Nearest].
+	///
+	/// Where both sheets have events at the same tick, the events are sorted
+	/// as in [Moment::sort_events]; use [Sheet::merge_with_order] to keep the
+	/// events of `self` first instead.
 	pub fn merge_with(&mut self, other: Self) {
-		if other.len() > self.len() {
-			let n_new = other.len() - self.len();
-			self.0.extend((0..n_new).map(|_| Moment::default()))
+		self.merge_with_order(other, EventOrder::Canonical);
+	}
+
+	/// Same as [Sheet::merge_with] but events at the same tick are ordered as
+	/// specified by `order`.
+	pub fn merge_with_order(&mut self, mut other: Self, order: EventOrder) {
+		self.match_resolution(&mut other);
+		self.len = self.len.max(other.len);
+		if other.moments.is_empty() {
+			return;
+		}
+		if self.moments.is_empty() {
+			self.moments = other.moments;
+			return;
 		}
 
-		for (i, moment) in other.0.into_iter().enumerate() {
-			for e in moment.events {
-				self[i].push(e);
-			}
+		let mut merged = Vec::with_capacity(self.moments.len() + other.moments.len());
+		let mut a = std::mem::take(&mut self.moments).into_iter().peekable();
+		let mut b = other.moments.into_iter().peekable();
+
+		loop {
+			let next = match (a.peek(), b.peek()) {
+				(Some(x), Some(y)) if x.tick == y.tick => {
+					let mut x = a.next().unwrap();
+					merge_events(&mut x.events, b.next().unwrap().events, order);
+					x
+				}
+				(Some(x), Some(y)) if x.tick > y.tick => b.next().unwrap(),
+				(Some(_), _) => a.next().unwrap(),
+				(None, Some(_)) => b.next().unwrap(),
+				(None, None) => break,
+			};
+			merged.push(next);
 		}
+
+		self.moments = merged;
+	}
+
+	/// Appends another [Sheet] to the end of `self`, destroying the other.
+	///
+	/// Every moment in `other` is shifted by [self.len()](Sheet::len) ticks.
+	/// Sheets of different resolutions are handled as in [Sheet::merge_with].
+	pub fn append(&mut self, mut other: Self) {
+		self.match_resolution(&mut other);
+		let offset = self.len;
+		self.moments.extend(other.moments.into_iter().map(|mut m| {
+			m.tick = m.tick.saturating_add(offset);
+			m
+		}));
+		self.len = self.len.saturating_add(other.len);
 	}
 
 	/// Returns an iterator over every moment in `self`.
+	///
+	/// Ticks with no events are skipped; use [Moment::tick] to find out where
+	/// a moment is.
 	pub fn iter(&self) -> std::slice::Iter<'_, Moment> {
-		self.0.iter()
+		self.moments.iter()
 	}
 
 	/// Returns an iterator over mutable references to the [Moment]s contained
 	/// in `self`.
+	///
+	/// As with [Sheet::iter], ticks with no events are skipped; to add events
+	/// at an empty tick, use [Sheet::insert].
 	pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, Moment> {
-		self.0.iter_mut()
+		self.moments.iter_mut()
 	}
 
-	/// Add a single [Moment] at the end of this sheet.
-	pub fn push(&mut self, m: Moment) {
-		self.0.push(m);
+	/// Appends a [Moment] as the last tick of this sheet, at
+	/// [self.len()](Sheet::len), whatever its own [tick](Moment::tick).
+	///
+	/// Every call makes the sheet one tick longer, even if `m` is empty. To
+	/// add a moment at its own tick, use [Sheet::push_at].
+	pub fn push(&mut self, mut m: Moment) {
+		m.tick = self.len;
+		self.len = self.len.saturating_add(1);
+		if !m.events.is_empty() {
+			self.moments.push(m);
+		}
+	}
+
+	/// Adds a [Moment] to this sheet at its own [tick](Moment::tick).
+	///
+	/// If there is already a moment at that tick, the events are appended to
+	/// it. The length of the sheet is extended to cover the moment, even if it
+	/// is empty.
+	pub fn push_at(&mut self, m: Moment) {
+		self.len = self.len.max(m.tick.saturating_add(1));
+		if m.events.is_empty() {
+			return;
+		}
+
+		match self.moments.last_mut() {
+			Some(last) if last.tick == m.tick => last.events.extend(m.events),
+			Some(last) if last.tick > m.tick => match self.position(m.tick) {
+				Ok(i) => self.moments[i].events.extend(m.events),
+				Err(i) => self.moments.insert(i, m),
+			},
+			_ => self.moments.push(m),
+		}
+	}
+
+	/// Adds a single [Event] at the given tick.
+	pub fn insert(&mut self, tick: u32, event: Event) {
+		self.push_at(Moment::with_events(tick, vec![event]));
+	}
+
+	/// Returns the [Moment] at `tick`, if there is one.
+	pub fn get(&self, tick: u32) -> Option<&Moment> {
+		self.position(tick).ok().map(|i| &self.moments[i])
+	}
+
+	/// Returns a mutable reference to the [Moment] at `tick`, if there is one.
+	pub fn get_mut(&mut self, tick: u32) -> Option<&mut Moment> {
+		self.position(tick).ok().map(move |i| &mut self.moments[i])
 	}
 
 	/// Transposes every note in this sheet.
@@ -130,13 +298,115 @@ impl Sheet {
 	/// Applies [Moment::transpose] on every item in `self`. see its
 	/// documentation for more info.
 	pub fn transpose(&mut self, shift: i8, transpose_ch9: bool) {
-		for m in &mut self.0 {
+		for m in &mut self.moments {
 			m.transpose(shift, transpose_ch9);
 		}
 	}
 
 	/// Returns a slice of every [Moment] in `self`. Equivalent to `&sheet[..]`.
 	pub fn as_moments(&self) -> &[Moment] {
-		&self.0[..]
+		&self.moments[..]
+	}
+
+	/// Returns an iterator over every event in `self` and the time it plays
+	/// at, computed with `timer` without sleeping. See [Render].
+	pub fn render<T: Timer>(&self, timer: T) -> Render<'_, T> {
+		Render::new(&self.moments, timer)
+	}
+
+	// Binary searches the stored moments for `tick`.
+	pub(crate) fn position(&self, tick: u32) -> Result<usize, usize> {
+		self.moments.binary_search_by_key(&tick, |m| m.tick)
+	}
+
+	// Returns the index of the first moment at or after `tick`.
+	pub(crate) fn lower_bound(&self, tick: u32) -> usize {
+		self.moments.partition_point(|m| m.tick < tick)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use midly::{num::u28, MetaMessage, TrackEventKind};
+
+	use super::*;
+
+	fn tempo(delta: u32, n: u32) -> TrackEvent<'static> {
+		TrackEvent {
+			delta: u28::new(delta),
+			kind: TrackEventKind::Meta(MetaMessage::Tempo(n.into())),
+		}
+	}
+
+	#[test]
+	fn sparse_from_track() {
+		let track = [tempo(0, 1), tempo(480, 2), tempo(480, 3)];
+		let sheet = Sheet::from(&track[..]);
+
+		assert_eq!(sheet.len(), 961);
+		assert_eq!(sheet.as_moments().len(), 3);
+		assert_eq!(sheet[480].events, vec![Event::Tempo(2)]);
+		assert!(sheet[479].is_empty());
+		assert_eq!(sheet[1..=480].len(), 1);
+		assert_eq!(sheet[480..].len(), 2);
+		// Empty ticks share one moment at tick 0.
+		assert_eq!(sheet[500].tick(), 0);
+		assert!(sheet.get(500).is_none());
+	}
+
+	#[test]
+	fn push_and_extend() {
+		let mut sheet = Sheet::from_moments([
+			Moment::with_events(4, vec![Event::Tempo(1)]),
+			Moment::with_events(0, vec![Event::Tempo(2)]),
+			Moment::with_events(4, vec![Event::Tempo(3)]),
+		]);
+		assert_eq!(sheet.len(), 5);
+		assert_eq!(sheet[4].events, vec![Event::Tempo(1), Event::Tempo(3)]);
+
+		// Moments are appended one tick after another, whatever their tick.
+		sheet.extend([
+			Moment::default(),
+			Moment::with_events(0, vec![Event::Tempo(4)]),
+		]);
+		assert_eq!(sheet.len(), 7);
+		assert_eq!(sheet[6].events, vec![Event::Tempo(4)]);
+		assert_eq!(sheet[6].tick(), 6);
+		let built = [
+			Moment::default(),
+			Moment::with_events(9, vec![Event::Tempo(5)]),
+		]
+		.into_iter()
+		.collect::<Sheet>();
+		assert_eq!(built.len(), 2);
+		assert_eq!(built[1].events, vec![Event::Tempo(5)]);
+
+		// Indexing an empty tick inserts a moment there.
+		sheet[5].events.push(Event::Tempo(6));
+		assert_eq!(sheet.get(5).unwrap().events, vec![Event::Tempo(6)]);
+		assert_eq!(sheet.len(), 7);
+
+		// Sheets are appended.
+		let other = sheet.clone();
+		sheet.extend([other]);
+		assert_eq!(sheet.len(), 14);
+		assert_eq!(sheet[7].events, vec![Event::Tempo(2)]);
+	}
+
+	#[test]
+	fn merge_and_append() {
+		let mut a = Sheet::from(&[tempo(10, 1), tempo(10, 2)][..]);
+		let b = Sheet::from(&[tempo(5, 3), tempo(5, 4), tempo(30, 5)][..]);
+
+		let mut seq = a.clone();
+		seq.append(b.clone());
+		assert_eq!(seq.len(), 21 + 41);
+		assert_eq!(seq[21 + 5].events, vec![Event::Tempo(3)]);
+
+		a.merge_with(b);
+		let ticks = a.iter().map(|m| m.tick()).collect::<Vec<_>>();
+		assert_eq!(ticks, [5, 10, 20, 40]);
+		assert_eq!(a[10].events, vec![Event::Tempo(1), Event::Tempo(4)]);
+		assert_eq!(a.len(), 41);
 	}
 }
@@ -1,66 +1,146 @@
 use std::collections::VecDeque;
 
-use crate::{Event, Moment, Sheet};
-
-#[derive(Eq, PartialEq, Copy, Clone, Debug)]
-struct TimeSignature {
-	// Beats per bar.
-	numerator: u8,
-	// Note of a beat. A negative power of 2.
-	denominator: u8,
+use crate::{BarMap, Moment, Sheet};
+
+/// A measure of a [Sheet], as yielded by [Bars] and [BarsRef].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Bar<M> {
+	/// The position of this bar in the iteration, starting from 0.
+	pub index: u32,
+	/// The bar number, as in [Position::bar](crate::Position::bar): starts
+	/// from 1, a pickup bar is 0.
+	pub number: u32,
+	/// The tick this bar starts at.
+	pub start: u32,
+	/// The length of this bar in ticks.
+	///
+	/// A bar ended early by a time signature change is shorter than its time
+	/// signature says; so is a pickup bar.
+	pub len: u32,
+	/// The time signature of this bar, as `(numerator, denominator)`; the
+	/// denominator is a negative power of 2, as in
+	/// [Event::TimeSignature](crate::Event::TimeSignature).
+	pub time_signature: (u8, u8),
+	/// The non-empty [Moment]s in this bar; they keep their absolute
+	/// [tick](Moment::tick).
+	pub moments: M,
 }
 
-impl TimeSignature {
-	fn bar_32s(&self) -> f32 {
-		let note_as_32s = 2_f32.powi(5_i32 - self.denominator as i32);
-		// let note_as_32s = 32.0 / self.denominator as f32;
-		self.numerator as f32 * note_as_32s
+impl<M> Bar<M> {
+	/// Returns the tick after the end of this bar.
+	pub fn end(&self) -> u32 {
+		self.start.saturating_add(self.len)
 	}
 }
 
-/// An iterator over bars in a MIDI [Sheet].
+// The bookkeeping shared by [Bars] and [BarsRef].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Cursor {
+	map: BarMap,
+	index: u32,
+	number: u32,
+	end: u32,
+}
+
+impl Cursor {
+	fn new(map: BarMap, end: u32) -> Self {
+		Self {
+			number: if map.pickup() > 0 { 0 } else { 1 },
+			map,
+			index: 0,
+			end,
+		}
+	}
+
+	fn next<M>(&mut self, moments: impl FnOnce(u32) -> M) -> Option<Bar<M>> {
+		let range = self.map.bar_range(self.number);
+		if range.start >= self.end {
+			return None;
+		}
+
+		let bar = Bar {
+			index: self.index,
+			number: self.number,
+			start: range.start,
+			len: range.end - range.start,
+			time_signature: self.map.time_signature_at(range.start),
+			moments: moments(range.end),
+		};
+		self.index += 1;
+		self.number += 1;
+		Some(bar)
+	}
+}
+
+/// An iterator over the bars of a [Sheet], consuming it.
+///
+/// Created with [Sheet::into_bars] or [Bars::new]. Bar lengths come from a
+/// [BarMap], see its documentation for details.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Bars {
-	time_sig: TimeSignature,
-	// beat_32s: u8,
-	tpb: f32,
+	cursor: Cursor,
 	buf: VecDeque<Moment>,
 }
 
+impl Bars {
+	/// Creates a [Bars] splitting `sheet` as described by `map`.
+	///
+	/// Use this instead of [Sheet::into_bars] for a sheet with a pickup bar,
+	/// see [BarMap::with_pickup].
+	pub fn new(sheet: Sheet, map: BarMap) -> Self {
+		Self {
+			cursor: Cursor::new(map, sheet.len),
+			buf: sheet.moments.into(),
+		}
+	}
+}
+
 impl Iterator for Bars {
-	type Item = Vec<Moment>;
+	type Item = Bar<Vec<Moment>>;
 
 	fn next(&mut self) -> Option<Self::Item> {
-		// Check if start of the bar has time signature.
-		let first = self.buf.pop_front()?;
-		if let Some(time_sig) = find_time_sig(&first) {
-			self.time_sig = time_sig;
-		}
-		if self.buf.is_empty() {
-			return Some(vec![first]);
-		}
+		let buf = &mut self.buf;
+		self.cursor.next(|end| {
+			let n = buf.partition_point(|m| m.tick < end);
+			buf.drain(..n).collect()
+		})
+	}
+}
 
-		let len_32nd = self.tpb / 8.0;
-		let chunk_len = (self.time_sig.bar_32s() * len_32nd) as usize;
-		let mut temp = Vec::with_capacity(chunk_len);
-		temp.push(first);
-
-		for _ in 1..chunk_len {
-			if let Some(moment) = self.buf.pop_front() {
-				let time_sig = find_time_sig(&moment);
-				temp.push(moment);
-				if let Some(time_sig) = time_sig {
-					if time_sig != self.time_sig {
-						self.time_sig = time_sig;
-						break;
-					}
-				}
-			} else {
-				break;
-			}
+/// An iterator over the bars of a [Sheet], borrowing it.
+///
+/// Created with [Sheet::bars] or [BarsRef::new]. Bar lengths come from a
+/// [BarMap], see its documentation for details.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BarsRef<'a> {
+	cursor: Cursor,
+	moments: &'a [Moment],
+}
+
+impl<'a> BarsRef<'a> {
+	/// Creates a [BarsRef] splitting `sheet` as described by `map`.
+	///
+	/// Use this instead of [Sheet::bars] for a sheet with a pickup bar, see
+	/// [BarMap::with_pickup].
+	pub fn new(sheet: &'a Sheet, map: BarMap) -> Self {
+		Self {
+			cursor: Cursor::new(map, sheet.len),
+			moments: &sheet.moments,
 		}
+	}
+}
+
+impl<'a> Iterator for BarsRef<'a> {
+	type Item = Bar<&'a [Moment]>;
 
-		Some(temp)
+	fn next(&mut self) -> Option<Self::Item> {
+		let moments = &mut self.moments;
+		self.cursor.next(|end| {
+			let n = moments.partition_point(|m| m.tick < end);
+			let (bar, rest) = moments.split_at(n);
+			*moments = rest;
+			bar
+		})
 	}
 }
 
@@ -71,26 +151,72 @@ impl Sheet {
 	/// - `ticks_per_beat`: Obtained from a [Header](midly::Header), same value
 	///   used for constructing a [Ticker](crate::timers::Ticker).
 	pub fn into_bars(self, ticks_per_beat: u16) -> Bars {
-		Bars {
-			tpb: ticks_per_beat as f32,
-			time_sig: TimeSignature {
-				numerator: 4,
-				denominator: 4,
-			},
-			// beat_32s: 24,
-			buf: self.0.into(),
-		}
+		let map = BarMap::new(&self, ticks_per_beat);
+		Bars::new(self, map)
+	}
+
+	/// Returns an iterator that yields measures (bars) from this sheet,
+	/// without consuming it.
+	///
+	/// # Arguments
+	/// - `ticks_per_beat`: Obtained from a [Header](midly::Header), same value
+	///   used for constructing a [Ticker](crate::timers::Ticker).
+	pub fn bars(&self, ticks_per_beat: u16) -> BarsRef<'_> {
+		BarsRef::new(self, BarMap::new(self, ticks_per_beat))
 	}
 }
 
-fn find_time_sig(m: &Moment) -> Option<TimeSignature> {
-	for e in &m.events {
-		if let Event::TimeSignature(n, d, ..) = e {
-			return Some(TimeSignature {
-				numerator: *n,
-				denominator: *d,
-			});
-		}
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::Event;
+
+	#[test]
+	fn bars() {
+		let sig = |tick, n, d| Moment::with_events(tick, vec![Event::TimeSignature(n, d, 24, 8)]);
+		// 7/8, then 3/16 from tick 1000, ending a bar of 7/8 early.
+		let sheet = Sheet::from_moments([
+			sig(0, 7, 3),
+			Moment::with_events(100, vec![Event::Marker(vec![])]),
+			sig(60 + 840 + 100, 3, 4),
+			Moment::new(60 + 840 + 100 + 100),
+		]);
+		// With a pickup of an eighth.
+		let map = BarMap::with_pickup(&sheet, 120, 60);
+
+		let bars = sheet
+			.bars(120)
+			.map(|b| (b.number, b.start, b.len))
+			.collect::<Vec<_>>();
+		assert_eq!(
+			bars,
+			[
+				(1, 0, 420),
+				(2, 420, 420),
+				(3, 840, 160),
+				(4, 1000, 90),
+				(5, 1090, 90)
+			]
+		);
+
+		let bars = BarsRef::new(&sheet, map.clone()).collect::<Vec<_>>();
+		assert_eq!(
+			bars.iter()
+				.map(|b| (b.index, b.number, b.start, b.len, b.time_signature))
+				.collect::<Vec<_>>(),
+			[
+				(0, 0, 0, 60, (7, 3)),
+				(1, 1, 60, 420, (7, 3)),
+				(2, 2, 480, 420, (7, 3)),
+				(3, 3, 900, 100, (7, 3)),
+				(4, 4, 1000, 90, (3, 4)),
+				(5, 5, 1090, 90, (3, 4)),
+			]
+		);
+		assert_eq!(bars[0].moments.len(), 1);
+		assert_eq!(bars[1].moments[0].tick, 100);
+
+		let owned = Bars::new(sheet.clone(), map).collect::<Vec<_>>();
+		assert!(owned.iter().zip(&bars).all(|(a, b)| a.moments == b.moments));
 	}
-	None
 }
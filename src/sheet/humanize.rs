@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+
+use super::Sheet;
+
+/// Settings for [Sheet::humanize].
+///
+/// The same settings, seed included, always give the same result.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Humanize {
+	/// The seed of the random number generator.
+	pub seed: u64,
+	/// The most a note can be moved, earlier or later, in ticks.
+	pub timing: u32,
+	/// The most the velocity of a note can change, up or down.
+	pub velocity: u8,
+	/// The channels affected, indexed by channel number.
+	pub channels: [bool; 16],
+}
+
+impl Humanize {
+	/// Creates a [Humanize] with the given seed and ranges, affecting every
+	/// channel.
+	pub fn new(seed: u64, timing: u32, velocity: u8) -> Self {
+		Self {
+			seed,
+			timing,
+			velocity,
+			channels: [true; 16],
+		}
+	}
+}
+
+// SplitMix64, small and good enough to sound random.
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+	pub(crate) fn next(&mut self) -> u64 {
+		self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+		let mut z = self.0;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+		z ^ (z >> 31)
+	}
+
+	// A number in `-range..=range`.
+	fn offset(&mut self, range: u32) -> i64 {
+		if range == 0 {
+			return 0;
+		}
+		let n = self.next() % (2 * range as u64 + 1);
+		n as i64 - range as i64
+	}
+}
+
+impl Sheet {
+	/// Randomly moves notes and changes their velocity, within the ranges in
+	/// `h`.
+	///
+	/// A note is moved as a whole, its NoteOff along with its NoteOn, so its
+	/// duration does not change; notes are never moved before tick 0, and a
+	/// note never overlaps the notes before and after it on the same key and
+	/// channel.
+	/// Velocities stay between 1 and 127. Only NoteOn and NoteOff messages
+	/// are changed, and notes are paired up as in [Sheet::notes] (a NoteOff
+	/// that does not end a note is removed, a note that is never ended gets
+	/// one at the end of the sheet).
+	pub fn humanize(&mut self, h: &Humanize) {
+		let mut rng = Rng(h.seed);
+		let mut notes = self.notes();
+
+		// A note can move between the end of the previous note on its key, as
+		// moved, and the start of the next one, as it was.
+		let mut next_start = vec![u32::MAX; notes.len()];
+		let mut next = HashMap::new();
+		for (i, note) in notes.iter().enumerate().rev() {
+			if let Some(j) = next.insert((note.channel, note.key), i) {
+				next_start[i] = notes[j].start_tick;
+			}
+		}
+		let mut prev_end = HashMap::new();
+
+		for (note, next_start) in notes.iter_mut().zip(next_start) {
+			let key = (note.channel, note.key);
+			if h.channels[note.channel.as_int() as usize] {
+				let start = note.start_tick as i64 + rng.offset(h.timing);
+				let start = start.clamp(0, u32::MAX as i64) as u32;
+				let latest = next_start.saturating_sub(note.duration);
+				let earliest = prev_end.get(&key).copied().unwrap_or(0);
+				note.start_tick = start.min(latest).max(earliest);
+				let vel = note.velocity.as_int() as i64 + rng.offset(h.velocity as u32);
+				note.velocity = (vel.clamp(1, 127) as u8).into();
+			}
+			prev_end.insert(key, note.end_tick());
+		}
+
+		self.replace_notes(&notes);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::Note;
+
+	#[test]
+	fn humanize() {
+		let notes = (0..100)
+			.map(|i| Note {
+				start_tick: i * 10,
+				duration: 5,
+				channel: (i as u8 % 2).into(),
+				key: (i as u8).into(),
+				velocity: 64.into(),
+				off_velocity: None,
+			})
+			.collect::<Vec<_>>();
+		let sheet = Sheet::from_notes(&notes);
+		let mut h = Humanize::new(7, 4, 10);
+		h.channels[1] = false;
+
+		let mut a = sheet.clone();
+		a.humanize(&h);
+		let mut b = sheet.clone();
+		b.humanize(&h);
+		assert_eq!(a, b);
+		assert_ne!(a, sheet);
+
+		let mut moved = 0;
+		for (old, new) in notes.iter().zip(a.notes()) {
+			assert_eq!(old.key, new.key);
+			assert_eq!(new.duration, 5);
+			let dt = new.start_tick.abs_diff(old.start_tick);
+			let dv = new.velocity.as_int().abs_diff(64);
+			assert!(dt <= 4 && dv <= 10);
+			if old.channel == 1 {
+				assert_eq!(old, &new);
+			}
+			moved += (dt > 0) as u32;
+		}
+		assert!(moved > 20);
+	}
+
+	#[test]
+	fn adjacent_notes() {
+		let note = |start_tick| Note {
+			start_tick,
+			duration: 10,
+			channel: 0.into(),
+			key: 60.into(),
+			velocity: 64.into(),
+			off_velocity: None,
+		};
+		let sheet = Sheet::from_notes(&[note(0), note(10), note(20)]);
+
+		for seed in 0..50 {
+			let mut sheet = sheet.clone();
+			sheet.humanize(&Humanize::new(seed, 8, 0));
+			let notes = sheet.notes();
+			assert_eq!(notes.len(), 3);
+			for pair in notes.windows(2) {
+				assert_eq!(pair[0].duration, 10);
+				assert!(pair[0].end_tick() <= pair[1].start_tick);
+			}
+		}
+	}
+}
@@ -2,16 +2,34 @@ use core::{
 	borrow::Borrow,
 	convert::TryFrom,
 	iter::{FromIterator, IntoIterator},
-	ops::{Deref, Index, IndexMut},
+	ops::{
+		Deref, Index, IndexMut, Range, RangeFrom, RangeFull, RangeInclusive, RangeTo,
+		RangeToInclusive,
+	},
 };
 
 use midly::TrackEvent;
 
 use crate::{Event, Moment, Sheet};
 
+// Returned when indexing a tick that holds no events.
+static EMPTY: Moment = Moment::new(0);
+
+/// Appends every [Moment] one tick after another, see [Sheet::push].
 impl Extend<Moment> for Sheet {
 	fn extend<T: IntoIterator<Item = Moment>>(&mut self, moments: T) {
-		self.0.extend(moments);
+		for m in moments {
+			self.push(m);
+		}
+	}
+}
+
+/// Appends every [Sheet] to the end of `self`, see [Sheet::append].
+impl Extend<Sheet> for Sheet {
+	fn extend<T: IntoIterator<Item = Sheet>>(&mut self, sheets: T) {
+		for sheet in sheets {
+			self.append(sheet);
+		}
 	}
 }
 
@@ -20,66 +38,138 @@ impl IntoIterator for Sheet {
 	type Item = Moment;
 
 	fn into_iter(self) -> Self::IntoIter {
-		self.0.into_iter()
+		self.moments.into_iter()
 	}
 }
 
 impl From<&[TrackEvent<'_>]> for Sheet {
 	fn from(events: &[TrackEvent<'_>]) -> Self {
-		let total_frames = events
-			.iter()
-			.map(|e| u32::from(e.delta) as usize)
-			.sum::<usize>()
-			+ 1;
-		let mut buf = vec![Moment::default(); total_frames];
-
-		let mut cur_pos = 0_usize;
+		let mut sheet = Self::new();
+		let mut cur_pos = 0_u32;
 
 		for event in events {
-			cur_pos += u32::from(event.delta) as usize;
+			cur_pos = cur_pos.saturating_add(u32::from(event.delta));
 			if let Ok(e) = Event::try_from(event.kind) {
-				buf[cur_pos].push(e);
+				sheet.insert(cur_pos, e);
 			}
 		}
 
-		Self(buf)
+		sheet.len = sheet.len.max(cur_pos.saturating_add(1));
+		sheet
 	}
 }
 
 impl Borrow<[Moment]> for Sheet {
 	fn borrow(&self) -> &[Moment] {
-		&self.0[..]
+		&self.moments[..]
 	}
 }
 
+/// Appends every [Moment] one tick after another, see [Sheet::push]; use
+/// [Sheet::from_moments] to keep the tick of every moment instead.
 impl FromIterator<Moment> for Sheet {
 	fn from_iter<I: IntoIterator<Item = Moment>>(it: I) -> Self {
-		Self(Vec::from_iter(it))
+		let mut sheet = Self::new();
+		sheet.extend(it);
+		sheet
 	}
 }
 
 impl Deref for Sheet {
 	type Target = [Moment];
 	fn deref(&self) -> &Self::Target {
-		&self.0
+		&self.moments
 	}
 }
 
-impl<I> Index<I> for Sheet
-where
-	Vec<Moment>: Index<I>,
-{
-	type Output = <Vec<Moment> as Index<I>>::Output;
-	fn index(&self, i: I) -> &Self::Output {
-		self.0.index(i)
+/// Returns the [Moment] at the given tick.
+///
+/// If there are no events at that tick, a shared empty moment is returned;
+/// its [tick](Moment::tick) is 0, not the tick asked for. Use [Sheet::get]
+/// to tell empty ticks apart.
+impl Index<usize> for Sheet {
+	type Output = Moment;
+	fn index(&self, tick: usize) -> &Moment {
+		u32::try_from(tick)
+			.ok()
+			.and_then(|tick| self.get(tick))
+			.unwrap_or(&EMPTY)
 	}
 }
 
-impl<I> IndexMut<I> for Sheet
-where
-	Vec<Moment>: IndexMut<I>,
-{
-	fn index_mut(&mut self, i: I) -> &mut Self::Output {
-		self.0.index_mut(i)
+/// Returns the [Moment] at the given tick.
+///
+/// If there are no events at that tick, an empty moment is stored there, so
+/// that events can be added to it; [Sheet::iter] yields it from then on.
+///
+/// # Panics
+/// Panics if `tick` is not less than [Sheet::len].
+impl IndexMut<usize> for Sheet {
+	fn index_mut(&mut self, tick: usize) -> &mut Moment {
+		assert!(
+			tick < self.len(),
+			"tick {tick} is out of range for a sheet of {} ticks",
+			self.len
+		);
+		let tick = tick as u32;
+		let i = match self.position(tick) {
+			Ok(i) => i,
+			Err(i) => {
+				self.moments.insert(i, Moment::new(tick));
+				i
+			}
+		};
+		&mut self.moments[i]
 	}
 }
+
+macro_rules! impl_range_index {
+	($($range:ty),+ $(,)?) => {
+		$(
+			/// Returns every stored [Moment] whose tick is within the range.
+			impl Index<$range> for Sheet {
+				type Output = [Moment];
+				fn index(&self, r: $range) -> &[Moment] {
+					let (start, end) = tick_bounds(self, &r);
+					&self.moments[start..end]
+				}
+			}
+
+			/// Returns every stored [Moment] whose tick is within the range.
+			impl IndexMut<$range> for Sheet {
+				fn index_mut(&mut self, r: $range) -> &mut [Moment] {
+					let (start, end) = tick_bounds(self, &r);
+					&mut self.moments[start..end]
+				}
+			}
+		)+
+	};
+}
+
+impl_range_index!(
+	Range<usize>,
+	RangeFrom<usize>,
+	RangeFull,
+	RangeInclusive<usize>,
+	RangeTo<usize>,
+	RangeToInclusive<usize>,
+);
+
+// Converts a range of ticks into a range of indices into `sheet.moments`.
+fn tick_bounds<R: core::ops::RangeBounds<usize>>(sheet: &Sheet, r: &R) -> (usize, usize) {
+	use core::ops::Bound;
+
+	let to_tick = |n: usize| u32::try_from(n).unwrap_or(u32::MAX);
+	let start = match r.start_bound() {
+		Bound::Included(&n) => sheet.lower_bound(to_tick(n)),
+		Bound::Excluded(&n) => sheet.lower_bound(to_tick(n).saturating_add(1)),
+		Bound::Unbounded => 0,
+	};
+	let end = match r.end_bound() {
+		Bound::Included(&n) => sheet.lower_bound(to_tick(n).saturating_add(1)),
+		Bound::Excluded(&n) => sheet.lower_bound(to_tick(n)),
+		Bound::Unbounded => sheet.moments.len(),
+	};
+
+	(start, end.max(start))
+}
@@ -0,0 +1,241 @@
+use std::collections::{HashMap, VecDeque};
+
+use midly::{
+	num::{u4, u7},
+	MidiMessage,
+};
+
+use super::Sheet;
+use crate::{Event, EventOrder, MidiEvent, Moment};
+
+/// A note with a start and a length, made of a NoteOn and the NoteOff that
+/// ends it.
+///
+/// Obtained with [Sheet::notes]; converted back with [Sheet::from_notes].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Note {
+	/// The tick of the NoteOn.
+	pub start_tick: u32,
+	/// The number of ticks until the NoteOff.
+	pub duration: u32,
+	/// The channel of the note.
+	pub channel: u4,
+	/// The key of the note.
+	pub key: u7,
+	/// The velocity of the NoteOn.
+	pub velocity: u7,
+	/// The velocity of the NoteOff, if the note was ended by one.
+	///
+	/// This is [None] if the note was ended by a NoteOn with a velocity of 0,
+	/// or if it was never ended.
+	pub off_velocity: Option<u7>,
+}
+
+impl Note {
+	/// Returns the tick of the NoteOff.
+	pub fn end_tick(&self) -> u32 {
+		self.start_tick.saturating_add(self.duration)
+	}
+
+	// The events starting and ending the note.
+	fn events(&self) -> (MidiEvent, MidiEvent) {
+		let on = MidiEvent {
+			channel: self.channel,
+			message: MidiMessage::NoteOn {
+				key: self.key,
+				vel: self.velocity,
+			},
+		};
+		let off = MidiEvent {
+			channel: self.channel,
+			message: match self.off_velocity {
+				Some(vel) => MidiMessage::NoteOff { key: self.key, vel },
+				None => MidiMessage::NoteOn {
+					key: self.key,
+					vel: 0.into(),
+				},
+			},
+		};
+		(on, off)
+	}
+}
+
+impl Sheet {
+	/// Pairs every NoteOn in `self` with the NoteOff that ends it.
+	///
+	/// The notes are sorted by their start tick, notes starting at the same
+	/// tick being in the order of their NoteOn.
+	///
+	/// # Notes
+	/// - A NoteOn with a velocity of 0 is a NoteOff.
+	/// - If the same key is pressed again on the same channel before being
+	///   released, the notes overlap and a NoteOff ends the earliest note
+	///   still sounding (first in, first out).
+	/// - A NoteOn that is never ended lasts until the last tick of the sheet
+	///   (see [Sheet::len]), so that writing the notes back does not make the
+	///   sheet longer.
+	/// - A NoteOff that does not end any note is ignored.
+	pub fn notes(&self) -> Vec<Note> {
+		let mut notes = Vec::new();
+		// Indices into `notes` of the notes still sounding, per channel and key.
+		let mut held = HashMap::<(u4, u7), VecDeque<usize>>::new();
+
+		for moment in &self.moments {
+			for event in &moment.events {
+				let (channel, key, vel, note_off) = match event {
+					Event::Midi(MidiEvent {
+						channel,
+						message: MidiMessage::NoteOn { key, vel },
+					}) => (*channel, *key, *vel, false),
+					Event::Midi(MidiEvent {
+						channel,
+						message: MidiMessage::NoteOff { key, vel },
+					}) => (*channel, *key, *vel, true),
+					_ => continue,
+				};
+
+				if !note_off && vel > 0 {
+					held.entry((channel, key))
+						.or_default()
+						.push_back(notes.len());
+					notes.push(Note {
+						start_tick: moment.tick,
+						duration: 0,
+						channel,
+						key,
+						velocity: vel,
+						off_velocity: None,
+					});
+				} else if let Some(i) = held.get_mut(&(channel, key)).and_then(|q| q.pop_front()) {
+					let note = &mut notes[i];
+					note.duration = moment.tick - note.start_tick;
+					note.off_velocity = note_off.then_some(vel);
+				}
+			}
+		}
+
+		for i in held.into_values().flatten() {
+			let note = &mut notes[i];
+			note.duration = self.len.saturating_sub(1).saturating_sub(note.start_tick);
+		}
+
+		notes
+	}
+
+	/// Creates a [Sheet] from notes.
+	///
+	/// Every note becomes a NoteOn and a NoteOff (a NoteOn with a velocity of
+	/// 0 if [Note::off_velocity] is [None]). At the same tick, NoteOffs come
+	/// before NoteOns, except for notes with a duration of 0.
+	pub fn from_notes(notes: &[Note]) -> Self {
+		// (tick, order, event); order puts NoteOffs first.
+		let mut events = Vec::with_capacity(notes.len() * 2);
+		for note in notes {
+			let (on, off) = note.events();
+			events.push((note.start_tick, 1, on));
+			events.push((note.end_tick(), if note.duration == 0 { 2 } else { 0 }, off));
+		}
+		events.sort_by_key(|(tick, order, _)| (*tick, *order));
+
+		let mut sheet = Self::new();
+		for (tick, _, event) in events {
+			sheet.push_at(Moment::with_events(tick, vec![Event::Midi(event)]));
+		}
+		sheet
+	}
+
+	// Replaces every NoteOn and NoteOff in `self` with `notes`; the new events
+	// come after the others at the same tick.
+	pub(crate) fn replace_notes(&mut self, notes: &[Note]) {
+		self.moments.retain_mut(|m| {
+			m.events.retain(|e| {
+				!matches!(
+					e,
+					Event::Midi(MidiEvent {
+						message: MidiMessage::NoteOn { .. } | MidiMessage::NoteOff { .. },
+						..
+					})
+				)
+			});
+			!m.events.is_empty()
+		});
+		self.merge_with_order(Self::from_notes(notes), EventOrder::Preserve);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn ev(tick: u32, message: MidiMessage) -> Moment {
+		Moment::with_events(
+			tick,
+			vec![Event::Midi(MidiEvent {
+				channel: 0.into(),
+				message,
+			})],
+		)
+	}
+
+	fn on(tick: u32, key: u8, vel: u8) -> Moment {
+		ev(
+			tick,
+			MidiMessage::NoteOn {
+				key: key.into(),
+				vel: vel.into(),
+			},
+		)
+	}
+
+	fn off(tick: u32, key: u8) -> Moment {
+		ev(
+			tick,
+			MidiMessage::NoteOff {
+				key: key.into(),
+				vel: 10.into(),
+			},
+		)
+	}
+
+	#[test]
+	fn pair_notes() {
+		let sheet = Sheet::from_moments([
+			off(0, 50),
+			on(0, 60, 100),
+			on(10, 60, 90),
+			on(20, 62, 80),
+			off(30, 60),
+			on(40, 60, 0),
+			on(40, 64, 70),
+			on(50, 62, 0),
+			Moment::new(99),
+		]);
+		let note = |start_tick, duration, key: u8, velocity: u8, off_velocity: Option<u8>| Note {
+			start_tick,
+			duration,
+			channel: 0.into(),
+			key: key.into(),
+			velocity: velocity.into(),
+			off_velocity: off_velocity.map(u7::from),
+		};
+
+		let notes = sheet.notes();
+		assert_eq!(
+			notes,
+			[
+				note(0, 30, 60, 100, Some(10)),
+				note(10, 30, 60, 90, None),
+				note(20, 30, 62, 80, None),
+				note(40, 59, 64, 70, None),
+			]
+		);
+
+		// The length is kept.
+		let back = Sheet::from_notes(&notes);
+		assert_eq!(back.notes(), notes);
+		assert_eq!(back.len(), 100);
+		let mut same = sheet.clone();
+		same.replace_notes(&notes);
+		assert_eq!(same.len(), 100);
+	}
+}
@@ -0,0 +1,167 @@
+use super::Sheet;
+
+/// Settings for [Sheet::quantize].
+///
+/// # Examples
+/// ```
+/// use nodi::Quantize;
+///
+/// // Eighth note triplets, moving notes 80% of the way.
+/// let q = Quantize {
+///     strength: 0.8,
+///     ..Quantize::new(480, 8).triplet()
+/// };
+/// assert_eq!(q.grid, 160.0);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Quantize {
+	/// The distance between two grid lines, in ticks; it does not need to be
+	/// a whole number.
+	pub grid: f64,
+	/// How far notes are moved towards the nearest grid line, from `0.0`
+	/// (not at all) to `1.0` (onto the grid line).
+	pub strength: f64,
+	/// How much every other grid line is delayed, as a fraction of
+	/// [Quantize::grid], from `0.0` (straight) to `1.0`. A value of `1/3`
+	/// gives a triplet feel.
+	pub swing: f64,
+	/// Whether the ends of notes are quantized too. If `false`, notes keep
+	/// their duration.
+	pub ends: bool,
+}
+
+impl Quantize {
+	/// Creates a [Quantize] with a grid of the given note value, at full
+	/// strength, without swing and keeping note durations.
+	///
+	/// # Arguments
+	/// - `ticks_per_beat`: Obtained from a [Header](midly::Header), same value
+	///   used for constructing a [Ticker](crate::timers::Ticker).
+	/// - `note`: The note value of the grid: `4` for quarter notes, `16` for
+	///   sixteenth notes and so on.
+	pub fn new(ticks_per_beat: u16, note: u32) -> Self {
+		Self {
+			grid: ticks_per_beat as f64 * 4.0 / note.max(1) as f64,
+			strength: 1.0,
+			swing: 0.0,
+			ends: false,
+		}
+	}
+
+	/// Makes the grid a triplet grid: three grid lines where there were two.
+	pub fn triplet(mut self) -> Self {
+		self.grid = self.grid * 2.0 / 3.0;
+		self
+	}
+
+	// Moves `tick` towards the nearest grid line.
+	fn snap(&self, tick: u32) -> u32 {
+		let t = tick as f64;
+		let pair = 2.0 * self.grid;
+		let base = (t / pair).floor() * pair;
+		let line = [base, base + self.grid * (1.0 + self.swing), base + pair]
+			.into_iter()
+			.min_by(|a, b| (a - t).abs().total_cmp(&(b - t).abs()))
+			.unwrap();
+
+		(t + (line.round() - t) * self.strength).round().max(0.0) as u32
+	}
+}
+
+impl Sheet {
+	/// Moves the notes of `self` towards a grid.
+	///
+	/// Only NoteOn and NoteOff messages are moved; controllers, meta events
+	/// and everything else stay in place. Notes are paired up as in
+	/// [Sheet::notes]: a NoteOff that does not end a note is removed, and a
+	/// note that is never ended gets one at the end of the sheet.
+	///
+	/// The grid starts at tick 0. With [Quantize::ends], a note whose end
+	/// would be moved to or before its start lasts one grid step instead.
+	pub fn quantize(&mut self, q: &Quantize) {
+		if q.grid <= 0.0 {
+			return;
+		}
+
+		let mut notes = self.notes();
+		for note in &mut notes {
+			let start = q.snap(note.start_tick);
+			if q.ends {
+				let end = q.snap(note.end_tick());
+				note.duration = if end > start {
+					end - start
+				} else {
+					(q.grid.round() as u32).max(1)
+				};
+			}
+			note.start_tick = start;
+		}
+
+		self.replace_notes(&notes);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use midly::MidiMessage;
+
+	use super::*;
+	use crate::{Event, MidiEvent, Moment, Note};
+
+	#[test]
+	fn quantize() {
+		let note = |start_tick, duration| Note {
+			start_tick,
+			duration,
+			channel: 0.into(),
+			key: 60.into(),
+			velocity: 100.into(),
+			off_velocity: Some(0.into()),
+		};
+		let cc = Event::Midi(MidiEvent {
+			channel: 0.into(),
+			message: MidiMessage::Controller {
+				controller: 7.into(),
+				value: 100.into(),
+			},
+		});
+		let mut sheet = Sheet::from_notes(&[note(5, 50), note(130, 100), note(230, 20)]);
+		sheet.push_at(Moment::with_events(7, vec![cc.clone()]));
+
+		let mut straight = sheet.clone();
+		straight.quantize(&Quantize::new(96, 8));
+		let starts = straight
+			.notes()
+			.iter()
+			.map(|n| (n.start_tick, n.duration))
+			.collect::<Vec<_>>();
+		assert_eq!(starts, [(0, 50), (144, 100), (240, 20)]);
+		assert_eq!(straight.get(7).map(|m| &m.events[..]), Some(&[cc][..]));
+
+		let mut swung = sheet.clone();
+		swung.quantize(&Quantize {
+			strength: 0.5,
+			swing: 1.0 / 3.0,
+			ends: true,
+			..Quantize::new(96, 8)
+		});
+		let starts = swung
+			.notes()
+			.iter()
+			.map(|n| (n.start_tick, n.duration))
+			.collect::<Vec<_>>();
+		assert_eq!(starts, [(3, 57), (145, 98), (243, 10)]);
+
+		let mut triplets = sheet;
+		triplets.quantize(&Quantize {
+			ends: true,
+			..Quantize::new(96, 8).triplet()
+		});
+		let starts = triplets
+			.notes()
+			.iter()
+			.map(|n| (n.start_tick, n.duration))
+			.collect::<Vec<_>>();
+		assert_eq!(starts, [(0, 64), (128, 96), (224, 32)]);
+	}
+}
@@ -0,0 +1,102 @@
+use super::Sheet;
+
+/// How ticks that fall between two ticks of the new resolution are rounded by
+/// [Sheet::resample].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum Rounding {
+	/// Round to the nearest tick, halves away from zero.
+	#[default]
+	Nearest,
+	/// Round towards zero.
+	Down,
+	/// Round away from zero.
+	Up,
+}
+
+impl Rounding {
+	// Computes `tick * to / from`.
+	pub(crate) fn rescale(self, tick: u32, from: u16, to: u16) -> u32 {
+		let (n, d) = (tick as u64 * to as u64, from as u64);
+		let t = match self {
+			Self::Nearest => (2 * n + d) / (2 * d),
+			Self::Down => n / d,
+			Self::Up => n.div_ceil(d),
+		};
+		u32::try_from(t).unwrap_or(u32::MAX)
+	}
+}
+
+impl Sheet {
+	/// Converts `self` from `from` ticks per beat to `to` ticks per beat.
+	///
+	/// Every moment is moved to its position in the new resolution, rounded
+	/// as specified; moments that end up on the same tick are merged, keeping
+	/// their order. Afterwards, [Sheet::ticks_per_beat] returns `to`.
+	///
+	/// # Panics
+	/// Panics if `from` or `to` is 0.
+	pub fn resample(&mut self, from: u16, to: u16, rounding: Rounding) {
+		assert!(from > 0 && to > 0, "ticks per beat must be greater than 0");
+		self.ticks_per_beat = Some(to);
+		if from == to {
+			return;
+		}
+
+		let old = std::mem::take(&mut self.moments);
+		let len = self.len;
+		self.len = 0;
+		for mut moment in old {
+			moment.tick = rounding.rescale(moment.tick, from, to);
+			self.push_at(moment);
+		}
+		self.len = self.len.max(Rounding::Up.rescale(len, from, to));
+	}
+
+	// Resamples `other` to the resolution of `self`, if both are known.
+	pub(crate) fn match_resolution(&mut self, other: &mut Self) {
+		match (self.ticks_per_beat, other.ticks_per_beat) {
+			(Some(to), Some(from)) if to != from => other.resample(from, to, Rounding::Nearest),
+			(None, Some(tpb)) => self.ticks_per_beat = Some(tpb),
+			_ => (),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{Event, Moment};
+
+	#[test]
+	fn resample() {
+		let sheet = Sheet::from_moments([
+			Moment::with_events(0, vec![Event::Tempo(1)]),
+			Moment::with_events(15, vec![Event::Tempo(2)]),
+			Moment::with_events(16, vec![Event::Tempo(3)]),
+			Moment::with_events(95, vec![Event::Tempo(4)]),
+		]);
+		let ticks = |s: &Sheet| s.iter().map(|m| m.tick()).collect::<Vec<_>>();
+
+		let mut up = sheet.clone();
+		up.resample(96, 960, Rounding::Nearest);
+		assert_eq!(ticks(&up), [0, 150, 160, 950]);
+		assert_eq!(up.len(), 960);
+
+		let mut down = sheet.clone();
+		down.resample(96, 10, Rounding::Nearest);
+		assert_eq!(ticks(&down), [0, 2, 10]);
+		assert_eq!(down[2].events, [Event::Tempo(2), Event::Tempo(3)]);
+		let mut floor = sheet.clone();
+		floor.resample(96, 10, Rounding::Down);
+		assert_eq!(ticks(&floor), [0, 1, 9]);
+		assert_eq!(floor.len(), 10);
+
+		let mut a = Sheet::from_moments([Moment::with_events(480, vec![Event::Tempo(5)])]);
+		a.set_ticks_per_beat(Some(960));
+		let mut b = sheet;
+		b.set_ticks_per_beat(Some(96));
+		b.merge_with(a);
+		assert_eq!(ticks(&b), [0, 15, 16, 48, 95]);
+		assert_eq!(b.ticks_per_beat(), Some(96));
+	}
+}
@@ -0,0 +1,253 @@
+use std::{fmt, io};
+
+use midly::{
+	num::{u15, u28},
+	Format, Header, MetaMessage, Smf, Timing, Track, TrackEvent, TrackEventKind,
+};
+
+use crate::{Event, Rounding, Sheet};
+
+/// An error returned by [Sheet::to_smf] when the ticks per beat do not fit in
+/// the header of a MIDI file.
+pub struct TicksPerBeatError;
+
+impl std::error::Error for TicksPerBeatError {}
+
+impl fmt::Debug for TicksPerBeatError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("ticks per beat must be less than 0x8000")
+	}
+}
+
+impl fmt::Display for TicksPerBeatError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("ticks per beat must be less than 0x8000")
+	}
+}
+
+impl Sheet {
+	/// Encodes `self` as a Standard MIDI File.
+	///
+	/// Delta times are computed from the [tick](crate::Moment::tick) of every
+	/// moment and every track is terminated with an End-of-Track event placed
+	/// at the end of the sheet.
+	///
+	/// # Arguments
+	/// - `format`: The layout of the file.
+	///   - [Format::SingleTrack] and [Format::Sequential]: Every event is
+	///     written into a single track.
+	///   - [Format::Parallel]: The first track holds every non-MIDI event
+	///     (tempo, time and key signatures, SysEx, text and other meta events),
+	///     followed by a track for every MIDI channel used in `self`.
+	/// - `ticks_per_beat`: Written to the header as [Timing::Metrical]. If the
+	///   sheet knows its own [ticks per beat](Sheet::ticks_per_beat) and they
+	///   differ, every tick is converted to this resolution as in
+	///   [Sheet::resample] with [Rounding::Nearest]; otherwise this should be
+	///   the same value the sheet was created with.
+	///
+	/// # Errors
+	/// Fails if the ticks per beat do not fit in 15 bits (`ticks_per_beat >=
+	/// 0x8000`).
+	///
+	/// # Notes
+	/// Gaps longer than a delta time can express (2^28 - 1 ticks) are bridged
+	/// with empty [Text](MetaMessage::Text) events.
+	pub fn to_smf(
+		&self,
+		format: Format,
+		ticks_per_beat: u16,
+	) -> Result<Smf<'_>, TicksPerBeatError> {
+		let tpb = u15::try_from(ticks_per_beat).ok_or(TicksPerBeatError)?;
+		let header = Header::new(format, Timing::Metrical(tpb));
+		// Resample on the fly, without copying the events.
+		let from = self
+			.ticks_per_beat
+			.filter(|&from| from > 0 && from != ticks_per_beat);
+		let scale = move |tick| {
+			from.map_or(tick, |from| {
+				Rounding::Nearest.rescale(tick, from, ticks_per_beat)
+			})
+		};
+		// As long as Sheet::resample would make the sheet.
+		let end = scale(self.end_tick()).max(from.map_or(0, |from| {
+			Rounding::Up
+				.rescale(self.len, from, ticks_per_beat)
+				.saturating_sub(1)
+		}));
+
+		let tracks = match format {
+			Format::SingleTrack | Format::Sequential => vec![encode_track(
+				self.iter()
+					.flat_map(|m| m.iter().map(move |e| (scale(m.tick), e))),
+				end,
+			)],
+			Format::Parallel => {
+				let mut tracks = vec![encode_track(
+					self.iter().flat_map(|m| {
+						m.iter()
+							.filter(|e| !matches!(e, Event::Midi(_)))
+							.map(move |e| (scale(m.tick), e))
+					}),
+					end,
+				)];
+
+				for ch in 0..16 {
+					let mut events = self
+						.iter()
+						.flat_map(|m| {
+							m.iter()
+								.filter(move |e| matches!(e, Event::Midi(msg) if msg.channel == ch))
+								.map(move |e| (scale(m.tick), e))
+						})
+						.peekable();
+
+					if events.peek().is_some() {
+						tracks.push(encode_track(events, end));
+					}
+				}
+
+				tracks
+			}
+		};
+
+		Ok(Smf { header, tracks })
+	}
+
+	/// Encodes `self` as a Standard MIDI File and writes it to `w`.
+	///
+	/// See [Sheet::to_smf] for the meaning of the arguments.
+	///
+	/// # Errors
+	/// Fails if writing fails, or with [io::ErrorKind::InvalidInput] if the
+	/// ticks per beat do not fit in 15 bits.
+	pub fn write_smf<W: io::Write>(
+		&self,
+		w: W,
+		format: Format,
+		ticks_per_beat: u16,
+	) -> io::Result<()> {
+		self.to_smf(format, ticks_per_beat)
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+			.write_std(w)
+	}
+
+	// Encodes every event in `self` into a single track.
+	pub(crate) fn to_track(&self) -> Track<'_> {
+		encode_track(
+			self.iter().flat_map(|m| m.iter().map(move |e| (m.tick, e))),
+			self.end_tick(),
+		)
+	}
+
+	// The tick of the End-of-Track event.
+	fn end_tick(&self) -> u32 {
+		self.moments
+			.last()
+			.map_or(0, |m| m.tick)
+			.max(self.len.saturating_sub(1))
+	}
+}
+
+// Turns `(absolute tick, event)` pairs into a track, ending at `end`.
+fn encode_track<'a, I>(events: I, end: u32) -> Track<'a>
+where
+	I: IntoIterator<Item = (u32, &'a Event)>,
+{
+	let mut track = Vec::new();
+	let mut last_tick = 0;
+
+	let mut push = |tick: u32, kind: TrackEventKind<'a>| {
+		let mut delta = tick - last_tick;
+		while delta > u28::max_value().as_int() {
+			track.push(TrackEvent {
+				delta: u28::max_value(),
+				kind: TrackEventKind::Meta(MetaMessage::Text(b"")),
+			});
+			delta -= u28::max_value().as_int();
+		}
+		track.push(TrackEvent {
+			delta: u28::new(delta),
+			kind,
+		});
+		last_tick = tick;
+	};
+
+	for (tick, e) in events {
+		push(tick, e.into());
+	}
+	push(end, TrackEventKind::Meta(MetaMessage::EndOfTrack));
+
+	track
+}
+
+#[cfg(test)]
+mod tests {
+	use midly::MidiMessage;
+
+	use super::*;
+	use crate::{MidiEvent, Moment};
+
+	fn note(ch: u8, key: u8) -> Event {
+		Event::Midi(MidiEvent {
+			channel: ch.into(),
+			message: MidiMessage::NoteOn {
+				key: key.into(),
+				vel: 100.into(),
+			},
+		})
+	}
+
+	#[test]
+	fn round_trip() {
+		let sheet = Sheet::from_moments([
+			Moment::with_events(
+				0,
+				vec![
+					Event::TrackName(b"piano".to_vec()),
+					Event::Tempo(500_000),
+					Event::SysEx(vec![0x7e, 0x7f, 0x09, 0x01, 0xf7]),
+					note(0, 60),
+				],
+			),
+			Moment::with_events(96, vec![note(1, 64)]),
+			Moment::with_events(
+				192,
+				vec![
+					Event::TimeSignature(3, 2, 24, 8),
+					Event::KeySignature(-2, false),
+				],
+			),
+			Moment::with_events(288, vec![Event::Lyric(b"la".to_vec()), note(0, 62)]),
+			Moment::new(400),
+		]);
+
+		let mut single = Vec::new();
+		sheet
+			.write_smf(&mut single, Format::SingleTrack, 96)
+			.unwrap();
+		let smf = Smf::parse(&single).unwrap();
+		assert_eq!(smf.header.timing, Timing::Metrical(96.into()));
+		assert_eq!(Sheet::single(&smf.tracks[0]), sheet);
+
+		let mut parallel = Vec::new();
+		sheet
+			.write_smf(&mut parallel, Format::Parallel, 96)
+			.unwrap();
+		let smf = Smf::parse(&parallel).unwrap();
+		assert_eq!(smf.tracks.len(), 3);
+		assert_eq!(Sheet::parallel(&smf.tracks), sheet);
+
+		assert!(sheet.to_smf(Format::SingleTrack, 0x8000).is_err());
+
+		// A sheet that knows its resolution is resampled to the one asked for.
+		let mut sheet = sheet;
+		sheet.set_ticks_per_beat(Some(96));
+		let smf = sheet.to_smf(Format::SingleTrack, 480).unwrap();
+		assert_eq!(smf.header.timing, Timing::Metrical(480.into()));
+		let mut expected = sheet.clone();
+		expected.resample(96, 480, Rounding::Nearest);
+		let mut back = Sheet::single(&smf.tracks[0]);
+		back.set_ticks_per_beat(Some(480));
+		assert_eq!(back, expected);
+	}
+}
@@ -0,0 +1,306 @@
+use std::io;
+
+use midly::{Format, Header, Smf, Timing};
+
+use crate::{event::merge_sources, Event, Moment, Rounding, Sheet};
+
+/// A track of a [Song], kept apart from the other tracks.
+#[derive(Default, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct SongTrack {
+	/// The events in this track.
+	pub sheet: Sheet,
+}
+
+impl SongTrack {
+	/// Creates a [SongTrack] holding `sheet`.
+	pub fn new(sheet: Sheet) -> Self {
+		Self { sheet }
+	}
+
+	/// Returns the name of this track: the text of its first
+	/// [TrackName](Event::TrackName) event.
+	pub fn name(&self) -> Option<&[u8]> {
+		self.find(|e| match e {
+			Event::TrackName(s) => Some(&s[..]),
+			_ => None,
+		})
+	}
+
+	/// Sets the name of this track, replacing the first
+	/// [TrackName](Event::TrackName) event or adding one at tick 0.
+	pub fn set_name(&mut self, name: impl Into<Vec<u8>>) {
+		let name = name.into();
+		self.replace(Event::TrackName(name), |e| matches!(e, Event::TrackName(_)));
+	}
+
+	/// Returns the instrument of this track: the text of its first
+	/// [InstrumentName](Event::InstrumentName) event.
+	pub fn instrument(&self) -> Option<&[u8]> {
+		self.find(|e| match e {
+			Event::InstrumentName(s) => Some(&s[..]),
+			_ => None,
+		})
+	}
+
+	/// Sets the instrument of this track, replacing the first
+	/// [InstrumentName](Event::InstrumentName) event or adding one at tick 0.
+	pub fn set_instrument(&mut self, instrument: impl Into<Vec<u8>>) {
+		let instrument = instrument.into();
+		self.replace(Event::InstrumentName(instrument), |e| {
+			matches!(e, Event::InstrumentName(_))
+		});
+	}
+
+	fn find<'a, T: ?Sized>(&'a self, f: impl Fn(&'a Event) -> Option<&'a T>) -> Option<&'a T> {
+		self.sheet.iter().flat_map(|m| m.iter()).find_map(f)
+	}
+
+	fn replace(&mut self, event: Event, f: impl Fn(&Event) -> bool) {
+		if let Some(e) = self
+			.sheet
+			.iter_mut()
+			.flat_map(|m| m.events.iter_mut())
+			.find(|e| f(e))
+		{
+			*e = event;
+		} else if let Some(m) = self.sheet.get_mut(0) {
+			m.events.insert(0, event);
+		} else {
+			self.sheet.insert(0, event);
+		}
+	}
+}
+
+/// A MIDI file with its tracks kept apart.
+///
+/// Unlike [Sheet::parallel] and [Sheet::sequential], a [Song] remembers which
+/// track every event belongs to, so tracks can be edited one by one and the
+/// file written back as it was read. Use [Song::sheet] to get a single
+/// [Sheet] for playback.
+///
+/// # Examples
+/// ```no_run
+/// use nodi::Song;
+/// // Assume `data` contains the bytes of some MIDI file (.mid).
+/// let data = Vec::new();
+/// let mut song = Song::parse(&data)?;
+///
+/// // Move the bass an octave down.
+/// for track in &mut song.tracks {
+///     if track.name() == Some(b"bass") {
+///         track.sheet.transpose(-12, false);
+///     }
+/// }
+///
+/// let sheet = song.sheet();
+/// // Play `sheet`, or write the song back:
+/// let mut file = Vec::new();
+/// song.write_smf(&mut file)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Song {
+	/// The layout of the tracks, from the header of the file.
+	pub format: Format,
+	/// The timing of the file, from its header.
+	pub timing: Timing,
+	/// The tracks, in the order they appear in the file.
+	pub tracks: Vec<SongTrack>,
+}
+
+impl Song {
+	/// Creates a [Song] with no tracks.
+	pub fn new(format: Format, timing: Timing) -> Self {
+		Self {
+			format,
+			timing,
+			tracks: Vec::new(),
+		}
+	}
+
+	/// Parses a Standard MIDI File.
+	pub fn parse(data: &[u8]) -> Result<Self, midly::Error> {
+		Smf::parse(data).map(|smf| Self::from(&smf))
+	}
+
+	/// Returns the ticks per beat of this song, if its timing is
+	/// [Timing::Metrical].
+	pub fn ticks_per_beat(&self) -> Option<u16> {
+		match self.timing {
+			Timing::Metrical(n) => Some(n.as_int()),
+			Timing::Timecode(..) => None,
+		}
+	}
+
+	/// Combines every track into a single [Sheet].
+	///
+	/// Tracks of a [Format::Parallel] song are merged as in
+	/// [Sheet::parallel]; otherwise they are appended end to end, as in
+	/// [Sheet::sequential].
+	pub fn sheet(&self) -> Sheet {
+		self.combine().0
+	}
+
+	// Combines the tracks into one sheet, along with the index of the track
+	// every event came from, moment by moment.
+	pub(crate) fn combine(&self) -> (Sheet, Vec<Vec<usize>>) {
+		let mut sheet = Sheet::new();
+		let mut moments = Vec::new();
+		for (i, t) in self.tracks.iter().enumerate() {
+			let track = &t.sheet;
+			// Resample on the fly, as Sheet::match_resolution would.
+			let scale = match (sheet.ticks_per_beat, track.ticks_per_beat) {
+				(Some(to), Some(from)) if to != from => Some((from, to)),
+				(None, Some(tpb)) => {
+					sheet.ticks_per_beat = Some(tpb);
+					None
+				}
+				_ => None,
+			};
+			let rescale = |tick, rounding: Rounding| {
+				scale.map_or(tick, |(from, to)| rounding.rescale(tick, from, to))
+			};
+			let offset = match self.format {
+				Format::Parallel => 0,
+				Format::SingleTrack | Format::Sequential => sheet.len,
+			};
+			let len = track
+				.moments
+				.last()
+				.map_or(0, |m| rescale(m.tick, Rounding::Nearest).saturating_add(1));
+			let len = len.max(rescale(track.len, Rounding::Up));
+			sheet.len = offset.saturating_add(len).max(sheet.len);
+			moments.extend(track.moments.iter().map(|m| {
+				let tick = rescale(m.tick, Rounding::Nearest).saturating_add(offset);
+				(tick, i, &m.events)
+			}));
+		}
+		// Stable, so tracks stay in order.
+		moments.sort_by_key(|(tick, ..)| *tick);
+
+		let mut tracks = Vec::new();
+		let mut moments = moments.into_iter().peekable();
+		while let Some((tick, i, events)) = moments.next() {
+			let mut sources = vec![(i, events.clone())];
+			while let Some((_, i, events)) = moments.next_if(|(t, ..)| *t == tick) {
+				// Moments of one track resampled onto the same tick are merged.
+				match sources.last_mut() {
+					Some((j, last)) if *j == i => last.extend_from_slice(events),
+					_ => sources.push((i, events.clone())),
+				}
+			}
+			let (t, events) = merge_sources(sources).into_iter().unzip();
+			sheet.moments.push(Moment::with_events(tick, events));
+			tracks.push(t);
+		}
+
+		(sheet, tracks)
+	}
+
+	/// Encodes `self` as a Standard MIDI File, one track for every
+	/// [SongTrack].
+	///
+	/// See [Sheet::to_smf] for how tracks are encoded.
+	pub fn to_smf(&self) -> Smf<'_> {
+		Smf {
+			header: Header::new(self.format, self.timing),
+			tracks: self.tracks.iter().map(|t| t.sheet.to_track()).collect(),
+		}
+	}
+
+	/// Encodes `self` as a Standard MIDI File and writes it to `w`.
+	///
+	/// # Errors
+	/// Fails if writing fails, or if the format is [Format::SingleTrack] and
+	/// there is more than one track.
+	pub fn write_smf<W: io::Write>(&self, w: W) -> io::Result<()> {
+		self.to_smf().write_std(w)
+	}
+}
+
+impl From<&Smf<'_>> for Song {
+	fn from(smf: &Smf<'_>) -> Self {
+		let mut song = Self::new(smf.header.format, smf.header.timing);
+		let tpb = song.ticks_per_beat();
+		song.tracks = smf
+			.tracks
+			.iter()
+			.map(|t| {
+				let mut sheet = Sheet::from(t.as_slice());
+				sheet.set_ticks_per_beat(tpb);
+				SongTrack::new(sheet)
+			})
+			.collect();
+		song
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use midly::MidiMessage;
+
+	use super::*;
+	use crate::MidiEvent;
+
+	#[test]
+	fn round_trip() {
+		let note = |ch: u8| {
+			Event::Midi(MidiEvent {
+				channel: ch.into(),
+				message: MidiMessage::NoteOn {
+					key: 60.into(),
+					vel: 100.into(),
+				},
+			})
+		};
+		let sheet = Sheet::from_moments([
+			Moment::with_events(0, vec![Event::Tempo(400_000), note(0)]),
+			Moment::with_events(48, vec![note(1)]),
+			Moment::new(96),
+		]);
+		let data = {
+			let mut buf = Vec::new();
+			sheet.write_smf(&mut buf, Format::Parallel, 96).unwrap();
+			buf
+		};
+
+		let mut song = Song::parse(&data).unwrap();
+		assert_eq!(song.tracks.len(), 3);
+		assert_eq!(song.ticks_per_beat(), Some(96));
+		assert_eq!(
+			song.sheet().iter().collect::<Vec<_>>(),
+			sheet.iter().collect::<Vec<_>>()
+		);
+
+		song.tracks[1].set_name("piano");
+		song.tracks[1].set_name("keys");
+		song.tracks[2].set_instrument("bass");
+		assert_eq!(song.tracks[1].name(), Some(&b"keys"[..]));
+		assert_eq!(
+			song.tracks[1].sheet[0].events[0],
+			Event::TrackName(b"keys".to_vec())
+		);
+		assert_eq!(song.tracks[2].instrument(), Some(&b"bass"[..]));
+
+		let mut buf = Vec::new();
+		song.write_smf(&mut buf).unwrap();
+		assert_eq!(Song::parse(&buf).unwrap(), song);
+
+		song.format = Format::Sequential;
+		assert_eq!(song.sheet().len(), 3 * 97);
+
+		// A track at twice the resolution is resampled; its moments at 95 and
+		// 96 land on the same tick and are merged.
+		song.format = Format::Parallel;
+		let mut fine = Sheet::from_moments([
+			Moment::with_events(95, vec![note(2)]),
+			Moment::with_events(96, vec![note(3)]),
+		]);
+		fine.set_ticks_per_beat(Some(192));
+		song.tracks.push(SongTrack::new(fine));
+		let (sheet, tracks) = song.combine();
+		assert_eq!(sheet.get(48).unwrap().events, [note(1), note(2), note(3)]);
+		assert_eq!(tracks[1], [2, 3, 3]);
+		assert_eq!(sheet.len(), 97);
+	}
+}
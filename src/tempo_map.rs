@@ -0,0 +1,198 @@
+use std::time::Duration;
+
+use midly::{Fps, Timing};
+
+use crate::{Event, Sheet};
+
+/// The tempo assumed until the first tempo change event, in microseconds per
+/// beat (120 BPM), as defined by the MIDI specification.
+pub const DEFAULT_TEMPO: u32 = 500_000;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Segment {
+	tick: u32,
+	// Time at `tick`, in microseconds, at a speed of 1.0.
+	micros: f64,
+	tempo: u32,
+	micros_per_tick: f64,
+}
+
+/// Maps MIDI ticks to wall-clock time and back.
+///
+/// A [TempoMap] is built once from a [Sheet] and the [Timing] found in the
+/// header of a MIDI file; every query afterwards runs in `O(log n)`, `n` being
+/// the number of tempo changes.
+///
+/// # Notes
+/// - Until the first tempo change, a tempo of [DEFAULT_TEMPO] is assumed.
+/// - With [Timing::Timecode], the length of a tick is fixed and tempo changes
+///   do not affect time; [TempoMap::tempo_at] still reports them.
+///
+/// # Examples
+/// ```no_run
+/// use std::time::Duration;
+/// use midly::Smf;
+/// use nodi::{Sheet, TempoMap};
+///
+/// let data = Vec::new();
+/// let Smf { header, tracks } = Smf::parse(&data)?;
+/// let sheet = Sheet::parallel(&tracks);
+/// let map = TempoMap::new(&sheet, header.timing);
+///
+/// println!("the song is {:?} long", map.duration());
+/// let tick = map.tick_at(Duration::from_secs_f64(83.4));
+/// println!("at 83.4s we are at tick {tick}, the tempo is {}", map.tempo_at(tick));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempoMap {
+	segments: Vec<Segment>,
+	len: u32,
+	/// Speed modifier, a value of `1.0` is the default and affects nothing.
+	///
+	/// This works the same as [Ticker::speed](crate::timers::Ticker::speed).
+	///
+	/// Important: Do not set to 0.0, this value is used as a denominator.
+	pub speed: f32,
+}
+
+impl TempoMap {
+	/// Builds a [TempoMap] from the tempo changes in `sheet`.
+	pub fn new(sheet: &Sheet, timing: Timing) -> Self {
+		let tick_len = |tempo: u32| match timing {
+			Timing::Metrical(tpb) => tempo as f64 / u16::from(tpb).max(1) as f64,
+			Timing::Timecode(fps, subframes) => {
+				let fps = match fps {
+					Fps::Fps29 => 30_000.0 / 1001.0,
+					other => other.as_int() as f64,
+				};
+				1_000_000.0 / fps / subframes.max(1) as f64
+			}
+		};
+
+		let mut segments = vec![Segment {
+			tick: 0,
+			micros: 0.0,
+			tempo: DEFAULT_TEMPO,
+			micros_per_tick: tick_len(DEFAULT_TEMPO),
+		}];
+
+		for moment in sheet.iter() {
+			for event in &moment.events {
+				if let Event::Tempo(tempo) = *event {
+					let last = segments.last_mut().unwrap();
+					let seg = Segment {
+						tick: moment.tick,
+						micros: last.micros
+							+ (moment.tick - last.tick) as f64 * last.micros_per_tick,
+						tempo,
+						micros_per_tick: tick_len(tempo),
+					};
+
+					if last.tick == seg.tick {
+						*last = seg;
+					} else {
+						segments.push(seg);
+					}
+				}
+			}
+		}
+
+		Self {
+			segments,
+			len: sheet.len,
+			speed: 1.0,
+		}
+	}
+
+	/// Returns the time tick number `tick` happens at.
+	pub fn time_at(&self, tick: u32) -> Duration {
+		let seg = self.segment_at(tick);
+		let micros = seg.micros + (tick - seg.tick) as f64 * seg.micros_per_tick;
+		self.to_duration(micros)
+	}
+
+	/// Returns the tick that is playing at the given time.
+	///
+	/// Times past the end of the sheet are extrapolated using the last tempo.
+	pub fn tick_at(&self, time: Duration) -> u32 {
+		let micros = time.as_secs_f64() * 1_000_000.0 * self.speed as f64;
+		let i = self
+			.segments
+			.partition_point(|s| s.micros <= micros)
+			.saturating_sub(1);
+		let seg = &self.segments[i];
+
+		if seg.micros_per_tick <= 0.0 {
+			return seg.tick;
+		}
+		// The epsilon guards against flooring 599.9999... down to 599.
+		let ticks = (micros - seg.micros) / seg.micros_per_tick + 1e-6;
+		seg.tick.saturating_add(ticks as u32)
+	}
+
+	/// Returns the tempo active at `tick`, in microseconds per beat.
+	pub fn tempo_at(&self, tick: u32) -> u32 {
+		self.segment_at(tick).tempo
+	}
+
+	/// Returns the total duration of the sheet this map was built from.
+	pub fn duration(&self) -> Duration {
+		self.time_at(self.len)
+	}
+
+	fn segment_at(&self, tick: u32) -> &Segment {
+		let i = self
+			.segments
+			.partition_point(|s| s.tick <= tick)
+			.saturating_sub(1);
+		&self.segments[i]
+	}
+
+	fn to_duration(&self, micros: f64) -> Duration {
+		let micros = micros / self.speed as f64;
+		if micros > 0.0 {
+			Duration::from_secs_f64(micros / 1_000_000.0)
+		} else {
+			Duration::default()
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::Moment;
+
+	fn sheet() -> Sheet {
+		// 120 BPM for 2 beats, then 60 BPM for 2 beats.
+		Sheet::from_moments([
+			Moment::with_events(480, vec![Event::Tempo(1_000_000)]),
+			Moment::new(959),
+		])
+	}
+
+	#[test]
+	fn metrical() {
+		let mut map = TempoMap::new(&sheet(), Timing::Metrical(240.into()));
+
+		assert_eq!(map.time_at(240), Duration::from_millis(500));
+		assert_eq!(map.time_at(480), Duration::from_secs(1));
+		assert_eq!(map.time_at(720), Duration::from_secs(2));
+		assert_eq!(map.duration(), Duration::from_secs(3));
+		assert_eq!(map.tick_at(Duration::from_millis(1500)), 600);
+		assert_eq!(map.tempo_at(479), DEFAULT_TEMPO);
+		assert_eq!(map.tempo_at(480), 1_000_000);
+
+		map.speed = 2.0;
+		assert_eq!(map.duration(), Duration::from_millis(1500));
+		assert_eq!(map.tick_at(Duration::from_millis(750)), 600);
+	}
+
+	#[test]
+	fn timecode() {
+		let map = TempoMap::new(&sheet(), Timing::Timecode(Fps::Fps25, 40));
+		assert_eq!(map.time_at(1000), Duration::from_secs(1));
+		assert_eq!(map.tick_at(Duration::from_millis(500)), 500);
+	}
+}
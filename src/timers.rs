@@ -3,17 +3,21 @@
 use std::{
 	convert::TryFrom,
 	fmt,
-	sync::mpsc::Receiver,
+	sync::{mpsc::Receiver, Arc, Mutex},
 	thread,
 	time::{Duration, Instant},
 };
 
-use midly::Timing;
+use midly::{Fps, SmpteTime, Timing};
 
-use crate::{Event, Moment, Timer};
+use crate::{Event, Moment, Timer, DEFAULT_TEMPO};
 
-/// An error that might arise while converting [Timing] to a [Ticker] or
-/// [FixedTempo].
+mod external;
+
+pub use external::{ClockInput, ExternalClock};
+
+/// An error that might arise while converting [Timing] to a [Ticker],
+/// [Smpte] or [FixedTempo].
 pub struct TimeFormatError;
 
 impl std::error::Error for TimeFormatError {}
@@ -48,13 +52,13 @@ pub struct Ticker {
 impl Ticker {
 	/// Create an instance of a [Ticker] with the given ticks-per-beat.
 	///
-	/// The tempo will be infinitely rapid, meaning no sleeps will happen.
-	/// However this is rarely an issue since a tempo change message will set
-	/// it, and this usually happens before any non-0 offset event.
+	/// The tempo is [DEFAULT_TEMPO] (120 BPM) until a tempo change message
+	/// sets it, as the MIDI specification defines and
+	/// [TempoMap](crate::TempoMap) assumes.
 	pub const fn new(ticks_per_beat: u16) -> Self {
 		Self {
 			ticks_per_beat,
-			micros_per_tick: 0.0,
+			micros_per_tick: DEFAULT_TEMPO as f64 / ticks_per_beat as f64,
 			last_instant: None,
 			speed: 1.0,
 		}
@@ -68,6 +72,8 @@ impl Ticker {
 	}
 
 	/// Upgrades `self` to a [ControlTicker].
+	#[deprecated = "use `PlayerHandle` instead"]
+	#[allow(deprecated)]
 	pub fn to_control(self, pause: Receiver<()>) -> ControlTicker {
 		ControlTicker {
 			speed: self.speed,
@@ -103,19 +109,34 @@ impl Timer for Ticker {
 		match self.last_instant {
 			Some(last_instant) => {
 				self.last_instant = Some(last_instant + t);
-				t = t.checked_sub(last_instant.elapsed()).unwrap_or(t);
+				// When late, don't sleep at all to catch up, as Smpte does.
+				t = t.checked_sub(last_instant.elapsed()).unwrap_or_default();
 			}
-			None => self.last_instant = Some(Instant::now()),
+			None => self.last_instant = Some(Instant::now() + t),
 		}
 
 		t
 	}
 
+	fn nominal_duration(&mut self, n_ticks: u32) -> Duration {
+		self.sleep_duration_without_readjustment(n_ticks)
+	}
+
+	fn set_speed(&mut self, speed: f32) {
+		self.speed = speed;
+	}
+
+	fn reset(&mut self) {
+		self.last_instant = None;
+	}
+
 	fn duration(&mut self, moments: &[Moment]) -> Duration {
 		let mut counter = Duration::default();
+		let mut last_tick = 0;
 
 		for moment in moments {
-			counter += self.sleep_duration_without_readjustment(1);
+			counter += self.sleep_duration_without_readjustment(moment.tick() - last_tick);
+			last_tick = moment.tick();
 
 			for event in &moment.events {
 				if let Event::Tempo(val) = event {
@@ -149,8 +170,8 @@ impl TryFrom<Timing> for Ticker {
 ///
 /// # Notes
 /// This type corresponds to [Timing::Timecode] and can be converted using
-/// [TryFrom::try_from]. Try to avoid using this timer because it's not tested
-/// (I couldn't find any MIDI files using [Timing::Timecode]).
+/// [TryFrom::try_from]. The length of a tick is rounded to a whole number of
+/// microseconds, which makes playback drift; prefer [Smpte], which is exact.
 pub struct FixedTempo(pub u64);
 
 impl TryFrom<Timing> for FixedTempo {
@@ -168,15 +189,349 @@ impl TryFrom<Timing> for FixedTempo {
 
 impl Timer for FixedTempo {
 	fn sleep_duration(&mut self, n_ticks: u32) -> Duration {
-		Duration::from_millis(self.0 * n_ticks as u64)
+		Duration::from_micros(self.0 * n_ticks as u64)
 	}
 
 	/// This function does nothing.
 	fn change_tempo(&mut self, _: u32) {}
 }
 
+/// Implements a SMPTE timecode [Timer].
+///
+/// Use this when the MIDI file header specifies the time format as being
+/// [Timing::Timecode]: every tick is a fixed fraction of a frame, and tempo
+/// changes do not affect timing.
+///
+/// Tick lengths are computed exactly, using rational arithmetic; in
+/// particular 29.97 fps ([Fps::Fps29], "drop-frame") is exactly
+/// `30000 / 1001` frames per second, and playback does not drift over time.
+///
+/// # SMPTE Offset
+/// A track may start with a [SMPTE Offset](crate::Event::SmpteOffset) event,
+/// giving the timecode tick 0 should play at. Set it with
+/// [Smpte::with_offset] (see [Smpte::find_offset]); then
+/// [Smpte::timecode_at] and [Smpte::tick_at_timecode] convert between ticks
+/// and timecode, for example to start playback at a given timecode with
+/// [Player::play_from](crate::Player::play_from).
+///
+/// The [Player](crate::Player) itself does not look at the offset: it has no
+/// timecode of its own to line up with, so [Player::play](crate::Player::play)
+/// starts at tick 0 whatever the offset, and the sheet plays the same. The
+/// offset only matters when following an outside timecode, such as a video
+/// or MIDI Time Code; convert that timecode with [Smpte::tick_at_timecode]
+/// and pass the tick to `play_from`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Smpte {
+	fps: Fps,
+	subframes: u8,
+	offset: Option<SmpteTime>,
+	last_instant: Option<Instant>,
+	// Ticks slept since the last reset; used to avoid accumulating rounding
+	// errors.
+	ticks: u64,
+	/// Speed modifier, a value of `1.0` is the default and affects nothing.
+	///
+	/// Important: Do not set to 0.0, this value is used as a denominator.
+	pub speed: f32,
+}
+
+// Returns the frame rate as a fraction: (frames, seconds).
+const fn frame_rate(fps: Fps) -> (u128, u128) {
+	match fps {
+		Fps::Fps29 => (30_000, 1001),
+		Fps::Fps24 => (24, 1),
+		Fps::Fps25 => (25, 1),
+		Fps::Fps30 => (30, 1),
+	}
+}
+
+impl Smpte {
+	/// Creates a [Smpte] timer with `subframes` ticks per frame.
+	///
+	/// # Panics
+	/// Panics if `subframes` is 0.
+	pub fn new(fps: Fps, subframes: u8) -> Self {
+		assert!(subframes > 0, "a frame must have at least 1 tick");
+		Self {
+			fps,
+			subframes,
+			offset: None,
+			last_instant: None,
+			ticks: 0,
+			speed: 1.0,
+		}
+	}
+
+	/// Sets the timecode tick 0 plays at.
+	pub fn with_offset(mut self, offset: SmpteTime) -> Self {
+		self.offset = Some(offset);
+		self
+	}
+
+	/// Returns the timecode tick 0 plays at, if set.
+	pub fn offset(&self) -> Option<SmpteTime> {
+		self.offset
+	}
+
+	/// Returns the first [SMPTE Offset](crate::Event::SmpteOffset) event in
+	/// `moments`, if there is one.
+	pub fn find_offset(moments: &[Moment]) -> Option<SmpteTime> {
+		moments
+			.iter()
+			.flat_map(|m| m.events.iter())
+			.find_map(|e| match e {
+				Event::SmpteOffset(t) => Some(*t),
+				_ => None,
+			})
+	}
+
+	/// Returns the frame rate of this timer.
+	pub fn fps(&self) -> Fps {
+		self.fps
+	}
+
+	/// Returns the number of ticks in a frame.
+	pub fn subframes(&self) -> u8 {
+		self.subframes
+	}
+
+	/// Returns the time tick number `tick` plays at, relative to tick 0.
+	///
+	/// [Smpte::speed] is not taken into account.
+	pub fn time_at(&self, tick: u32) -> Duration {
+		nanos_to_duration(self.nanos(tick as u64))
+	}
+
+	/// Returns the tick that plays at `time`, relative to tick 0.
+	///
+	/// [Smpte::speed] is not taken into account.
+	pub fn tick_at(&self, time: Duration) -> u32 {
+		let (frames, secs) = frame_rate(self.fps);
+		let ticks = time.as_nanos() * frames * self.subframes as u128 / (secs * 1_000_000_000);
+		u32::try_from(ticks).unwrap_or(u32::MAX)
+	}
+
+	/// Returns the timecode tick number `tick` plays at, as a [Duration]
+	/// since `00:00:00:00`.
+	///
+	/// This is [Smpte::time_at] plus the offset, if there is one.
+	pub fn timecode_at(&self, tick: u32) -> Duration {
+		self.offset.map_or(Duration::ZERO, timecode_to_duration) + self.time_at(tick)
+	}
+
+	/// Returns the tick that plays at `timecode`.
+	///
+	/// Timecodes before the offset give 0.
+	pub fn tick_at_timecode(&self, timecode: SmpteTime) -> u32 {
+		let start = self.offset.map_or(Duration::ZERO, timecode_to_duration);
+		self.tick_at(timecode_to_duration(timecode).saturating_sub(start))
+	}
+
+	// The time of `ticks` ticks in nanoseconds, rounded down.
+	fn nanos(&self, ticks: u64) -> u128 {
+		let (frames, secs) = frame_rate(self.fps);
+		ticks as u128 * secs * 1_000_000_000 / (frames * self.subframes as u128)
+	}
+
+	fn with_speed(&self, t: Duration) -> Duration {
+		if self.speed == 1.0 {
+			t
+		} else {
+			t.div_f64(self.speed as f64)
+		}
+	}
+}
+
+/// Converts a SMPTE timecode to a [Duration] since `00:00:00:00`.
+///
+/// With [Fps::Fps29], the timecode is assumed to be drop-frame: frame numbers
+/// 0 and 1 are skipped at the start of every minute, except every tenth
+/// minute.
+pub fn timecode_to_duration(t: SmpteTime) -> Duration {
+	let minutes = t.hour() as u128 * 60 + t.minute() as u128;
+	let seconds = minutes * 60 + t.second() as u128;
+	let (frames, secs) = frame_rate(t.fps());
+	let n = match t.fps() {
+		Fps::Fps29 => seconds * 30 - 2 * (minutes - minutes / 10),
+		_ => seconds * frames,
+	} + t.frame() as u128;
+
+	// In hundredths of a frame.
+	let n = n * 100 + t.subframe() as u128;
+	nanos_to_duration(n * secs * 1_000_000_000 / (frames * 100))
+}
+
+fn nanos_to_duration(nanos: u128) -> Duration {
+	Duration::new(
+		(nanos / 1_000_000_000) as u64,
+		(nanos % 1_000_000_000) as u32,
+	)
+}
+
+impl Timer for Smpte {
+	fn sleep_duration(&mut self, n_ticks: u32) -> Duration {
+		let next = self.ticks + n_ticks as u64;
+		let nanos = self.nanos(next) - self.nanos(self.ticks);
+		self.ticks = next;
+		let mut t = self.with_speed(nanos_to_duration(nanos));
+
+		match self.last_instant {
+			Some(last_instant) => {
+				self.last_instant = Some(last_instant + t);
+				// When late, don't sleep at all to catch up.
+				t = t.checked_sub(last_instant.elapsed()).unwrap_or_default();
+			}
+			None => self.last_instant = Some(Instant::now() + t),
+		}
+
+		t
+	}
+
+	/// This function does nothing; tempo does not affect timecode.
+	fn change_tempo(&mut self, _: u32) {}
+
+	fn nominal_duration(&mut self, n_ticks: u32) -> Duration {
+		self.with_speed(self.time_at(n_ticks))
+	}
+
+	fn set_speed(&mut self, speed: f32) {
+		self.speed = speed;
+	}
+
+	fn reset(&mut self) {
+		self.last_instant = None;
+		self.ticks = 0;
+	}
+
+	fn duration(&mut self, moments: &[Moment]) -> Duration {
+		let last = moments.last().map_or(0, |m| m.tick());
+		self.with_speed(self.time_at(last))
+	}
+}
+
+impl TryFrom<Timing> for Smpte {
+	type Error = TimeFormatError;
+
+	/// Tries to create a [Smpte] timer from the provided [Timing].
+	///
+	/// # Errors
+	/// Will return an error if the given [Timing] is not [Timing::Timecode], or
+	/// if it has 0 ticks per frame.
+	fn try_from(t: Timing) -> Result<Self, Self::Error> {
+		match t {
+			Timing::Timecode(fps, subframes) if subframes > 0 => Ok(Self::new(fps, subframes)),
+			_ => Err(TimeFormatError),
+		}
+	}
+}
+
+/// A clock that only moves when told to.
+///
+/// Cloning a [VirtualClock] gives another view of the same clock. It is
+/// advanced by a [VirtualTimer] and read by, for example, a
+/// [Recorder](crate::Recorder).
+#[derive(Debug, Clone, Default)]
+pub struct VirtualClock(Arc<Mutex<Duration>>);
+
+impl VirtualClock {
+	/// Creates a new [VirtualClock] at zero.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Returns the current time of the clock.
+	pub fn now(&self) -> Duration {
+		*self.0.lock().unwrap_or_else(|e| e.into_inner())
+	}
+
+	/// Moves the clock forward by `t`.
+	pub fn advance(&self, t: Duration) {
+		*self.0.lock().unwrap_or_else(|e| e.into_inner()) += t;
+	}
+
+	/// Sets the clock to `t`.
+	pub fn set(&self, t: Duration) {
+		*self.0.lock().unwrap_or_else(|e| e.into_inner()) = t;
+	}
+}
+
+/// A [Timer] that never sleeps; it advances a [VirtualClock] instead.
+///
+/// The wrapped timer is only used to compute durations (see
+/// [Timer::nominal_duration]), so a whole track can be played instantly
+/// while keeping the timeline it would have had. This is mostly useful in
+/// tests, together with a [Recorder](crate::Recorder).
+///
+/// # Examples
+/// ```
+/// use std::time::Duration;
+/// use nodi::{timers::{Ticker, VirtualClock, VirtualTimer}, Event, Moment, Player, Recorder};
+///
+/// let clock = VirtualClock::new();
+/// let timer = VirtualTimer::new(Ticker::with_initial_tempo(4, 500_000), clock.clone());
+/// let mut player = Player::new(timer, Recorder::new(clock.clone()));
+///
+/// let sheet = [Moment::new(0), Moment::new(8)];
+/// player.play(&sheet)?;
+/// assert_eq!(clock.now(), Duration::from_secs(1));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct VirtualTimer<T: Timer> {
+	inner: T,
+	clock: VirtualClock,
+}
+
+impl<T: Timer> VirtualTimer<T> {
+	/// Creates a [VirtualTimer] that advances `clock` as `inner` would sleep.
+	pub fn new(inner: T, clock: VirtualClock) -> Self {
+		Self { inner, clock }
+	}
+
+	/// Returns the clock advanced by this timer.
+	pub fn clock(&self) -> &VirtualClock {
+		&self.clock
+	}
+
+	/// Returns the wrapped timer.
+	pub fn into_inner(self) -> T {
+		self.inner
+	}
+}
+
+impl<T: Timer> Timer for VirtualTimer<T> {
+	/// Advances the clock by `n_ticks` and returns a zero duration.
+	fn sleep_duration(&mut self, n_ticks: u32) -> Duration {
+		self.clock.advance(self.inner.nominal_duration(n_ticks));
+		Duration::ZERO
+	}
+
+	fn change_tempo(&mut self, tempo: u32) {
+		self.inner.change_tempo(tempo);
+	}
+
+	fn nominal_duration(&mut self, n_ticks: u32) -> Duration {
+		self.inner.nominal_duration(n_ticks)
+	}
+
+	fn set_speed(&mut self, speed: f32) {
+		self.inner.set_speed(speed);
+	}
+
+	fn reset(&mut self) {
+		self.inner.reset();
+	}
+
+	fn duration(&mut self, moments: &[Moment]) -> Duration {
+		self.inner.duration(moments)
+	}
+}
+
 /// A [Timer] that lets you toggle playback.
 ///
+/// Deprecated: use a [PlayerHandle](crate::PlayerHandle) instead; it works
+/// with every [Timer] and can also stop, seek and change the speed.
+///
 /// This type works exactly like [Ticker], but it checks for messages
 /// on a [Receiver] and toggles playback if there is one.
 ///
@@ -191,6 +546,7 @@ impl Timer for FixedTempo {
 /// receiver is poisoned, see the [mpsc](std::sync::mpsc) documentation for
 /// more.
 #[derive(Debug)]
+#[deprecated = "use `PlayerHandle` instead"]
 pub struct ControlTicker {
 	ticks_per_beat: u16,
 	micros_per_tick: f64,
@@ -203,17 +559,17 @@ pub struct ControlTicker {
 	pub pause: Receiver<()>,
 }
 
+#[allow(deprecated)]
 impl ControlTicker {
 	/// Create an instance of [ControlTicker] with the given ticks-per-beat.
-	/// The tempo will be infinitely rapid, meaning no sleeps will happen.
-	/// However this is rarely an issue since a tempo change message will set
-	/// it, and this usually happens before any non-0 offset event.
+	/// The tempo is [DEFAULT_TEMPO] (120 BPM) until a tempo change message
+	/// sets it.
 	pub fn new(ticks_per_beat: u16, pause: Receiver<()>) -> Self {
 		Self {
 			ticks_per_beat,
 			pause,
 			last_instant: None,
-			micros_per_tick: 0.0,
+			micros_per_tick: DEFAULT_TEMPO as f64 / ticks_per_beat as f64,
 			speed: 1.0,
 		}
 	}
@@ -248,6 +604,7 @@ impl ControlTicker {
 	}
 }
 
+#[allow(deprecated)]
 impl Timer for ControlTicker {
 	fn change_tempo(&mut self, tempo: u32) {
 		let micros_per_tick = tempo as f64 / self.ticks_per_beat as f64;
@@ -260,14 +617,27 @@ impl Timer for ControlTicker {
 		match self.last_instant {
 			Some(last_instant) => {
 				self.last_instant = Some(last_instant + t);
-				t = t.checked_sub(last_instant.elapsed()).unwrap_or(t);
+				// When late, don't sleep at all to catch up, as Smpte does.
+				t = t.checked_sub(last_instant.elapsed()).unwrap_or_default();
 			}
-			None => self.last_instant = Some(Instant::now()),
+			None => self.last_instant = Some(Instant::now() + t),
 		}
 
 		t
 	}
 
+	fn nominal_duration(&mut self, n_ticks: u32) -> Duration {
+		self.sleep_duration_without_readjustment(n_ticks)
+	}
+
+	fn set_speed(&mut self, speed: f32) {
+		self.speed = speed;
+	}
+
+	fn reset(&mut self) {
+		self.last_instant = None;
+	}
+
 	/// Same with [Ticker::sleep], except it checks if there are any messages on
 	/// [self.pause], if there is a message, waits for another one before
 	/// continuing with the sleep.
@@ -291,9 +661,11 @@ impl Timer for ControlTicker {
 
 	fn duration(&mut self, moments: &[Moment]) -> Duration {
 		let mut counter = Duration::default();
+		let mut last_tick = 0;
 
 		for moment in moments {
-			counter += self.sleep_duration_without_readjustment(1);
+			counter += self.sleep_duration_without_readjustment(moment.tick() - last_tick);
+			last_tick = moment.tick();
 
 			for event in &moment.events {
 				if let Event::Tempo(val) = event {
@@ -349,3 +721,97 @@ fn spin_lock(t: Duration) {
 
 #[cfg(not(any(doc, test, feature = "hybrid-sleep")))]
 pub(crate) use thread::sleep;
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{Sheet, TempoMap};
+
+	#[test]
+	fn fixed_tempo() {
+		let mut timer = FixedTempo::try_from(Timing::Timecode(Fps::Fps25, 40)).unwrap();
+		assert_eq!(timer.sleep_duration(25), Duration::from_millis(25));
+	}
+
+	#[test]
+	fn duration_from_zero() {
+		// Leading silence counts.
+		let moments = [Moment::new(48), Moment::new(96)];
+		let mut ticker = Ticker::with_initial_tempo(96, 500_000);
+		assert_eq!(ticker.duration(&moments), Duration::from_millis(500));
+		let mut timer = Smpte::new(Fps::Fps24, 4);
+		assert_eq!(timer.duration(&moments[..1]), Duration::from_millis(500));
+
+		// A second late, the ticker does not sleep either.
+		ticker.last_instant = Instant::now().checked_sub(Duration::from_secs(1));
+		assert_eq!(ticker.sleep_duration(96), Duration::ZERO);
+		assert!(ticker.sleep_duration(96) < Duration::from_millis(500));
+
+		// Without a tempo change, the ticker agrees with a tempo map.
+		let map = TempoMap::new(&Sheet::new(), Timing::Metrical(96.into()));
+		assert_eq!(Ticker::new(96).duration(&moments), map.time_at(96));
+	}
+
+	#[test]
+	fn smpte_exact() {
+		let mut timer = Smpte::try_from(Timing::Timecode(Fps::Fps29, 80)).unwrap();
+		// One hour of 29.97 fps is 107_892 frames.
+		let frames = 107_892 * 80;
+		assert_eq!(
+			timer.time_at(frames),
+			Duration::from_nanos(3_599_996_400_000)
+		);
+		assert_eq!(
+			timer.tick_at(Duration::from_nanos(3_599_996_400_000)),
+			frames
+		);
+
+		// No drift, even though a tick is not a whole number of nanoseconds.
+		let ticks = 17_982 * 80;
+		let total = (0..ticks)
+			.map(|_| timer.nominal_duration(1))
+			.sum::<Duration>();
+		assert!(timer.time_at(ticks) - total < Duration::from_millis(1));
+		timer.last_instant = Some(Instant::now() + Duration::from_secs(10_000));
+		let total = (0..ticks)
+			.map(|_| timer.sleep_duration(1))
+			.sum::<Duration>();
+		assert_eq!(total, Duration::from_nanos(599_999_400_000));
+
+		let mut timer = Smpte::new(Fps::Fps24, 4);
+		assert_eq!(timer.time_at(96), Duration::from_secs(1));
+
+		// A second late: the next frames are not slept.
+		timer.last_instant = Instant::now().checked_sub(Duration::from_secs(1));
+		assert_eq!(timer.sleep_duration(48), Duration::ZERO);
+		assert_eq!(timer.sleep_duration(24), Duration::ZERO);
+		// The lost time is caught up.
+		assert!(timer.sleep_duration(48) < Duration::from_millis(500));
+	}
+
+	#[test]
+	fn smpte_offset() {
+		// Drop-frame: 00:01:00;02 is the first frame of the second minute.
+		let t = SmpteTime::new(0, 1, 0, 2, 0, Fps::Fps29).unwrap();
+		assert_eq!(
+			timecode_to_duration(t),
+			Duration::from_nanos(1800 * 1_000_000_000 * 1001 / 30_000)
+		);
+		let t = SmpteTime::new(0, 10, 0, 0, 0, Fps::Fps29).unwrap();
+		assert_eq!(
+			timecode_to_duration(t),
+			Duration::from_nanos(17_982 * 1_000_000_000 * 1001 / 30_000)
+		);
+
+		let offset = SmpteTime::new(1, 0, 0, 0, 0, Fps::Fps25).unwrap();
+		let moments = [Moment::with_events(0, vec![Event::SmpteOffset(offset)])];
+		let timer = Smpte::new(Fps::Fps25, 40).with_offset(Smpte::find_offset(&moments).unwrap());
+		assert_eq!(timer.timecode_at(500), Duration::from_millis(3_600_500));
+		let t = SmpteTime::new(1, 0, 2, 12, 50, Fps::Fps25).unwrap();
+		assert_eq!(timer.tick_at_timecode(t), 2500);
+		assert_eq!(
+			timer.tick_at_timecode(SmpteTime::new(0, 0, 0, 0, 0, Fps::Fps25).unwrap()),
+			0
+		);
+	}
+}
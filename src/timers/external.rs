@@ -0,0 +1,451 @@
+use std::{
+	sync::{Arc, Condvar, Mutex, MutexGuard},
+	time::{Duration, Instant},
+};
+
+use midly::{
+	live::{LiveEvent, SystemCommon, SystemRealtime},
+	num::u14,
+};
+
+use crate::{
+	player::{WeakHandle, CLOCKS_PER_SPP, PPQN},
+	PlayerHandle, Timer,
+};
+// The weight of a new measurement in the smoothed clock period.
+const SMOOTHING: f64 = 1.0 / 8.0;
+// Gaps longer than this between two clocks are dropouts, not a tempo.
+const MAX_PERIOD: Duration = Duration::from_secs(1);
+
+/// A [Timer] that follows an external MIDI beat clock.
+///
+/// Timing Clock messages (24 per beat), Start, Stop, Continue and Song
+/// Position Pointer messages are fed to it through a [ClockInput], from a
+/// MIDI input port or any other source. The period of the clock is smoothed
+/// to hide jitter, and clock ticks are mapped to the ticks per beat of the
+/// sheet; tempo changes in the sheet and
+/// [speed changes](crate::PlayerHandle::set_speed) are ignored, the clock
+/// sets the tempo.
+///
+/// The timer waits while the clock is stopped, so nothing is played before
+/// the first Start or Continue message. To also start, stop and seek the
+/// [Player](crate::Player) when the clock master does, give one of its
+/// [PlayerHandle]s to [ClockInput::follow].
+///
+/// # Examples
+/// ```no_run
+/// use nodi::{timers::ExternalClock, Connection, Player, Sheet};
+///
+/// fn play_along<C: Connection>(con: C, sheet: &Sheet) -> Result<(), Box<dyn std::error::Error>> {
+///     let timer = ExternalClock::new(480);
+///     let input = timer.input();
+///     let mut player = Player::new(timer, con);
+///     let handle = player.handle();
+///     input.follow(&handle);
+///
+///     // Feed the messages of a MIDI input port to `input`, for example with
+///     // `midir`:
+///     // midi_in.connect(&port, "clock", move |_, msg, _| input.receive_bytes(msg), ())?;
+///     player.play(sheet)?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ExternalClock {
+	shared: Arc<Shared>,
+	ticks_per_beat: u64,
+	// The tick of the sheet the last sleep ends at.
+	cursor: u64,
+}
+
+/// Feeds MIDI clock messages to an [ExternalClock].
+///
+/// Cloning a [ClockInput] gives another input to the same clock.
+#[derive(Debug, Clone)]
+pub struct ClockInput {
+	shared: Arc<Shared>,
+	ticks_per_beat: u64,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+	state: Mutex<State>,
+	cvar: Condvar,
+}
+
+#[derive(Debug)]
+struct State {
+	running: bool,
+	// The song position in Timing Clocks, counted from tick 0.
+	clocks: u64,
+	// When the last Timing Clock was received.
+	last: Option<Instant>,
+	// The first Timing Clock after Start, Continue or a Song Position Pointer
+	// falls on the song position instead of moving it.
+	pending: bool,
+	// The smoothed time between two Timing Clocks, in seconds.
+	period: f64,
+	measured: bool,
+	// The tick the timer has to jump to on its next reset.
+	locate: Option<u64>,
+	handle: WeakHandle,
+}
+
+impl Default for State {
+	fn default() -> Self {
+		Self {
+			running: false,
+			clocks: 0,
+			last: None,
+			pending: false,
+			// 120 BPM until the first clocks arrive.
+			period: 0.5 / PPQN as f64,
+			measured: false,
+			locate: None,
+			handle: WeakHandle::default(),
+		}
+	}
+}
+
+impl Shared {
+	fn lock(&self) -> MutexGuard<'_, State> {
+		self.state.lock().unwrap_or_else(|e| e.into_inner())
+	}
+}
+
+impl ExternalClock {
+	/// Creates an [ExternalClock] for a sheet with the given ticks per beat.
+	///
+	/// The clock is stopped until a Start or Continue message is received.
+	pub fn new(ticks_per_beat: u16) -> Self {
+		Self {
+			shared: Arc::default(),
+			ticks_per_beat: ticks_per_beat.max(1) as u64,
+			cursor: 0,
+		}
+	}
+
+	/// Returns an input feeding this clock.
+	pub fn input(&self) -> ClockInput {
+		ClockInput {
+			shared: Arc::clone(&self.shared),
+			ticks_per_beat: self.ticks_per_beat,
+		}
+	}
+
+	/// Returns the current tempo of the clock, in microseconds per beat.
+	pub fn tempo(&self) -> u32 {
+		(self.shared.lock().period * PPQN as f64 * 1e6).round() as u32
+	}
+
+	fn remaining(&self, s: &State) -> Duration {
+		let target = (self.cursor * PPQN) as f64 / self.ticks_per_beat as f64;
+		let mut pos = s.clocks as f64;
+		if let (true, false, Some(last)) = (s.running, s.pending, s.last) {
+			// Between two clocks, assume the tempo holds; never run more than
+			// a clock ahead of the master.
+			let elapsed = Instant::now().saturating_duration_since(last).as_secs_f64();
+			pos += (elapsed / s.period).min(1.0);
+		}
+		Duration::from_secs_f64((target - pos).max(0.0) * s.period)
+	}
+
+	// How long to wait before looking at the clock again, at most a clock;
+	// `None` once the clock has reached the cursor.
+	fn step(&self, s: &State) -> Option<Duration> {
+		let clock = Duration::from_secs_f64(s.period);
+		if !s.running {
+			return Some(clock);
+		}
+		let t = self.remaining(s);
+		(!t.is_zero()).then(|| t.min(clock))
+	}
+}
+
+impl Timer for ExternalClock {
+	/// Returns the time until the clock reaches the tick `n_ticks` after the
+	/// previous one, if it goes on at its current tempo.
+	fn sleep_duration(&mut self, n_ticks: u32) -> Duration {
+		self.cursor += n_ticks as u64;
+		self.remaining(&self.shared.lock())
+	}
+
+	/// Does nothing: the tempo follows the clock.
+	fn change_tempo(&mut self, _: u32) {}
+
+	fn nominal_duration(&mut self, n_ticks: u32) -> Duration {
+		let s = self.shared.lock();
+		Duration::from_secs_f64(
+			n_ticks as f64 * PPQN as f64 / self.ticks_per_beat as f64 * s.period,
+		)
+	}
+
+	/// Moves to the song position set by the last Start or Song Position
+	/// Pointer message, if it was not used yet, or back to tick 0.
+	///
+	/// The [Player](crate::Player) followed with [ClockInput::follow] also
+	/// resets its timer when it resumes after a pause; the position is kept
+	/// then.
+	fn reset(&mut self) {
+		let (locate, handle) = {
+			let mut s = self.shared.lock();
+			(s.locate.take(), s.handle.clone())
+		};
+		match locate {
+			Some(tick) => self.cursor = tick,
+			None if handle.is_running() => (),
+			None => self.cursor = 0,
+		}
+	}
+
+	/// Waits until the clock reaches the tick `n_ticks` after the previous
+	/// one, blocking while the clock is stopped.
+	fn sleep(&mut self, n_ticks: u32) {
+		self.cursor += n_ticks as u64;
+		let mut s = self.shared.lock();
+		while let Some(step) = self.step(&s) {
+			s = if s.running {
+				self.shared
+					.cvar
+					.wait_timeout(s, step)
+					.map_or_else(|e| e.into_inner().0, |(s, _)| s)
+			} else {
+				self.shared.cvar.wait(s).unwrap_or_else(|e| e.into_inner())
+			};
+		}
+	}
+
+	/// Like `sleep`, but waits through `wait` a clock at a time, so that the
+	/// [Player](crate::Player) can still be paused, stopped or moved while the
+	/// clock is stopped.
+	fn sleep_with(&mut self, n_ticks: u32, wait: &mut dyn FnMut(Duration) -> bool) {
+		self.cursor += n_ticks as u64;
+		loop {
+			let step = self.step(&self.shared.lock());
+			// Once the clock is there, still look for commands sent with it,
+			// such as the seek of a Start.
+			if !wait(step.unwrap_or_default()) || step.is_none() {
+				return;
+			}
+		}
+	}
+}
+
+impl ClockInput {
+	/// Makes the [Player](crate::Player) behind `handle` follow the clock
+	/// master: Start plays from the beginning, Stop pauses, Continue resumes
+	/// and a Song Position Pointer seeks.
+	///
+	/// The clock does not count as a handle: keep a [PlayerHandle] alive
+	/// while following, as a player paused with no handle left stops (see
+	/// [PlayerHandle]).
+	pub fn follow(&self, handle: &PlayerHandle) {
+		self.shared.lock().handle = handle.downgrade();
+		self.shared.cvar.notify_all();
+	}
+
+	/// Handles a system real-time message received now.
+	pub fn receive(&self, msg: SystemRealtime) {
+		self.receive_at(msg, Instant::now());
+	}
+
+	/// Handles a system real-time message received at `time`.
+	///
+	/// Messages other than Timing Clock, Start, Stop and Continue are
+	/// ignored.
+	pub fn receive_at(&self, msg: SystemRealtime, time: Instant) {
+		let mut s = self.shared.lock();
+		let handle = s.handle.clone();
+		match msg {
+			SystemRealtime::TimingClock if s.running => {
+				if s.pending {
+					s.pending = false;
+				} else {
+					s.clocks += 1;
+					if let Some(last) = s.last {
+						let period = time.saturating_duration_since(last);
+						if !period.is_zero() && period <= MAX_PERIOD {
+							let period = period.as_secs_f64();
+							if s.measured {
+								s.period += (period - s.period) * SMOOTHING;
+							} else {
+								s.period = period;
+								s.measured = true;
+							}
+						}
+					}
+				}
+				s.last = Some(time);
+				self.shared.cvar.notify_all();
+			}
+			SystemRealtime::Start => {
+				s.clocks = 0;
+				s.locate = Some(0);
+				drop(s);
+				// Seek before starting, so the player does not play on from
+				// where it was.
+				handle.seek(0);
+				self.start(&mut self.shared.lock());
+				handle.resume();
+			}
+			SystemRealtime::Continue => {
+				self.start(&mut s);
+				drop(s);
+				handle.resume();
+			}
+			SystemRealtime::Stop => {
+				drop(s);
+				// Pause first, so the player does not play on while stopped.
+				handle.pause();
+				let mut s = self.shared.lock();
+				s.running = false;
+				s.last = None;
+			}
+			_ => (),
+		}
+	}
+
+	/// Handles a Song Position Pointer message: moves to `pos` sixteenth
+	/// notes from the start of the song.
+	pub fn song_position(&self, pos: u14) {
+		let clocks = pos.as_int() as u64 * CLOCKS_PER_SPP;
+		let tick = clocks * self.ticks_per_beat / PPQN;
+		let mut s = self.shared.lock();
+		s.clocks = clocks;
+		s.locate = Some(tick);
+		s.pending = true;
+		s.last = None;
+		let handle = s.handle.clone();
+		drop(s);
+		handle.seek(u32::try_from(tick).unwrap_or(u32::MAX));
+	}
+
+	/// Parses a MIDI message received now and handles it if it is a system
+	/// real-time message or a Song Position Pointer; anything else is
+	/// ignored.
+	pub fn receive_bytes(&self, bytes: &[u8]) {
+		match LiveEvent::parse(bytes) {
+			Ok(LiveEvent::Realtime(msg)) => self.receive(msg),
+			Ok(LiveEvent::Common(SystemCommon::SongPosition(pos))) => self.song_position(pos),
+			_ => (),
+		}
+	}
+
+	fn start(&self, s: &mut State) {
+		s.running = true;
+		s.pending = true;
+		s.last = None;
+		self.shared.cvar.notify_all();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::thread;
+
+	use midly::MidiMessage;
+
+	use super::*;
+	use crate::{Connection, ConnectionError, Event, MidiEvent, Moment, Player};
+
+	#[test]
+	fn follow_clock() {
+		let mut timer = ExternalClock::new(96);
+		let input = timer.input();
+		// In the future, so no time passes between two clocks while testing.
+		let base = Instant::now() + Duration::from_secs(60);
+		let ms = |n| base + Duration::from_millis(n);
+		let millis = |d: Duration| (d.as_secs_f64() * 1e3).round() as u64;
+
+		input.receive_at(SystemRealtime::Start, ms(0));
+		for t in [0, 20, 40] {
+			input.receive_at(SystemRealtime::TimingClock, ms(t));
+		}
+		timer.reset();
+		// Tick 16 is clock 4; two clocks were counted.
+		assert_eq!(millis(timer.sleep_duration(16)), 40);
+		assert_eq!(timer.tempo(), 480_000);
+
+		// A late clock only moves the tempo by an eighth of the difference.
+		input.receive_at(SystemRealtime::TimingClock, ms(68));
+		assert_eq!(millis(timer.sleep_duration(4)), 42);
+		// Behind the clock: no sleeping.
+		for t in [89, 110, 131] {
+			input.receive_at(SystemRealtime::TimingClock, ms(t));
+		}
+		assert_eq!(timer.sleep_duration(0), Duration::ZERO);
+
+		input.receive(SystemRealtime::Stop);
+		input.receive_bytes(&[0xF2, 4, 0]);
+		timer.reset();
+		// Clock 26, two clocks after the sixteenth at tick 96.
+		assert_eq!(millis(timer.sleep_duration(8)), 42);
+		let waiting = thread::spawn(move || {
+			let t = Instant::now();
+			timer.sleep(0);
+			(t.elapsed(), timer)
+		});
+		thread::sleep(Duration::from_millis(50));
+		input.receive(SystemRealtime::Continue);
+		for _ in 0..3 {
+			thread::sleep(Duration::from_millis(10));
+			input.receive(SystemRealtime::TimingClock);
+		}
+		let (elapsed, mut timer) = waiting.join().unwrap();
+		assert!(elapsed >= Duration::from_millis(70));
+
+		// With no song position to move to, a reset starts over from tick 0,
+		// which the clock is past.
+		timer.reset();
+		assert_eq!(timer.sleep_duration(8), Duration::ZERO);
+	}
+
+	#[derive(Debug, Default)]
+	struct Log(Vec<Instant>);
+
+	impl Connection for Log {
+		fn play(&mut self, msg: MidiEvent) -> Result<(), ConnectionError> {
+			if let MidiMessage::NoteOn { .. } = msg.message {
+				self.0.push(Instant::now());
+			}
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn wait_for_start() {
+		let note = |tick| {
+			Moment::with_events(
+				tick,
+				vec![Event::Midi(MidiEvent {
+					channel: 0.into(),
+					message: MidiMessage::NoteOn {
+						key: 60.into(),
+						vel: 100.into(),
+					},
+				})],
+			)
+		};
+		let timer = ExternalClock::new(96);
+		let input = timer.input();
+		let mut player = Player::new(timer, Log::default());
+		let handle = player.handle();
+		input.follow(&handle);
+		let playing = thread::spawn(move || {
+			player.play(&[note(0), note(24)]).unwrap();
+			player
+		});
+
+		// At 120 BPM, both notes would be played by now.
+		thread::sleep(Duration::from_millis(200));
+		let start = Instant::now();
+		input.receive(SystemRealtime::Start);
+		for _ in 0..8 {
+			thread::sleep(Duration::from_millis(5));
+			input.receive(SystemRealtime::TimingClock);
+		}
+		let player = playing.join().unwrap();
+		assert_eq!(player.con.0.len(), 2);
+		assert!(player.con.0.iter().all(|&t| t >= start));
+	}
+}